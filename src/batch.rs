@@ -0,0 +1,190 @@
+use crate::{
+    config::Config, job::Job, preset::Preset, progress::Mode, schedule::Schedule, MainError,
+    MainResult,
+};
+use futures::stream::{self, StreamExt};
+use serde::Deserialize;
+use std::{
+    ffi::OsStr,
+    fs::read_to_string,
+    iter::once,
+    path::{Path, PathBuf},
+};
+use twinicodo::twitter::TwitterClient;
+
+/// Jobs read from a TOML or JSON file.
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub jobs: Vec<JobSpec>,
+    /// Recurring broadcasts expanded into jobs after `jobs`
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobSpec {
+    pub name: Option<String>,
+    pub query: String,
+    /// Queries searched in addition to `query`
+    #[serde(default)]
+    pub queries: Vec<String>,
+    pub since: String,
+    pub until: String,
+    /// Time at vpos 0
+    pub anchor: Option<String>,
+    pub episode: Option<u32>,
+    pub preset: Option<String>,
+    pub output: Option<String>,
+    pub archive: Option<PathBuf>,
+}
+
+impl Manifest {
+    /// All jobs including the ones expanded from schedules.
+    pub fn specs(&self) -> Vec<JobSpec> {
+        self.jobs
+            .iter()
+            .cloned()
+            .chain(self.schedules.iter().flat_map(|s| s.jobs()))
+            .collect()
+    }
+
+    pub fn load(path: &Path) -> MainResult<Self> {
+        let s = read_to_string(path)?;
+        Ok(if path.extension() == Some(OsStr::new("json")) {
+            serde_json::from_str(&s)?
+        } else {
+            toml::from_str(&s)?
+        })
+    }
+}
+
+impl JobSpec {
+    fn label(&self, index: usize) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("#{}", index + 1))
+    }
+
+    /// `overrides` are conversion options given by flags, which take precedence over presets.
+    fn job(&self, cfg: &Config, overrides: &Preset) -> MainResult<Job> {
+        Ok(Job {
+            queries: once(&self.query).chain(&self.queries).cloned().collect(),
+            since: self.since.clone(),
+            until: self.until.clone(),
+            anchor: self.anchor.clone(),
+            episode: self.episode,
+            preset: cfg.preset(self.preset.as_deref())?.merge(overrides.clone()),
+            output: self.output.clone(),
+            archive: self.archive.clone(),
+        })
+    }
+}
+
+/// Runs jobs in order with at most `parallel` jobs at once and prints a summary.
+/// When `only` is not empty, only jobs with the names or the numbers like `#2` are run.
+pub async fn run(
+    manifest: Manifest,
+    client: &TwitterClient,
+    cfg: &Config,
+    overrides: &Preset,
+    mode: Mode,
+    parallel: usize,
+    only: &[String],
+) -> MainResult<()> {
+    // lines updated in place would overwrite each other
+    let mode = if parallel > 1 && mode == Mode::Text {
+        Mode::Lines
+    } else {
+        mode
+    };
+    let specs = manifest.specs();
+    let jobs = specs
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.label(i), s))
+        .filter(|(label, _)| only.is_empty() || only.contains(label))
+        .map(|(label, s)| {
+            let job = s.job(cfg, overrides).and_then(|j| j.validate().map(|_| j));
+            (label, job)
+        })
+        .collect::<Vec<_>>();
+
+    let results = stream::iter(jobs)
+        .map(|(label, job)| async move {
+            let result = match job {
+                Ok(job) => job.run(client, mode, Some(label.clone())).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result.as_ref() {
+                eprintln!("{}: {}", label, err);
+            }
+            (label, result)
+        })
+        .buffered(parallel.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    let rows = results
+        .iter()
+        .map(|(label, r)| match r {
+            Ok(o) => [
+                label.to_string(),
+                "ok".to_string(),
+                o.count.to_string(),
+                o.trimmed.to_string(),
+                o.output
+                    .as_ref()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|| "(no tweet found)".to_string()),
+            ],
+            Err(err) => [
+                label.to_string(),
+                "failed".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+                err.to_string(),
+            ],
+        })
+        .collect::<Vec<_>>();
+    print_table(&["JOB", "STATUS", "COMMENTS", "TRIMMED", "OUTPUT"], &rows);
+
+    let failed = results
+        .iter()
+        .filter(|(_, r)| r.is_err())
+        .map(|(label, _)| label.as_str())
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        return Ok(());
+    }
+    eprintln!(
+        "To retry failed jobs: --only '{}'",
+        failed.join("' --only '")
+    );
+    Err(MainError::BatchFailed(failed.len()))
+}
+
+fn print_table(header: &[&str; 5], rows: &[[String; 5]]) {
+    let mut widths = [0; 5];
+    for (i, w) in widths.iter_mut().enumerate() {
+        *w = rows
+            .iter()
+            .map(|r| r[i].chars().count())
+            .chain(once(header[i].len()))
+            .max()
+            .unwrap_or(0);
+    }
+    let line = |cells: Vec<&str>| {
+        let s = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(c, w)| format!("{:<1$}", c, w))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", s.trim_end());
+    };
+    line(header.to_vec());
+    for r in rows {
+        line(r.iter().map(|c| c.as_str()).collect());
+    }
+}
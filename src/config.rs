@@ -1,42 +1,204 @@
+use crate::{
+    preset::Preset,
+    secrets::{self, Storage},
+};
+use derive_more::From;
 use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, io::ErrorKind};
+use thiserror::Error;
 use twinicodo::twitter::{Auth, Cookie, CookieError};
 
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
+const BEARER_ENV: &str = "TWINICODO_BEARER";
+const CSRF_ENV: &str = "TWINICODO_CSRF";
+const COOKIE_ENV: &str = "TWINICODO_COOKIE";
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Debug, Error, From)]
+pub enum Error {
+    #[error("{0}")]
+    Confy(confy::ConfyError),
+    #[error("{0}")]
+    Secrets(secrets::Error),
+    #[error("preset not found: {0}")]
+    #[from(ignore)]
+    PresetNotFound(String),
+}
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Config {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub secrets: Storage,
+    #[serde(default)]
+    pub defaults: Preset,
+    #[serde(default)]
+    pub presets: BTreeMap<String, Preset>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(skip)]
+    passphrase: Option<String>,
+    #[serde(skip)]
+    interactive: bool,
+    #[serde(skip)]
+    unlocked: bool,
+}
+
+impl Config {
+    /// When `interactive` is false, a passphrase must be given by the environment variable.
+    pub fn load(interactive: bool) -> Result<Self, Error> {
+        let mut cfg: Self = confy::load(APP_NAME)?;
+        cfg.interactive = interactive;
+        if cfg.profiles.is_empty() {
+            // configs written before profiles were introduced hold a single profile at the top level
+            let legacy: Profile = confy::load(APP_NAME)?;
+            if legacy.init {
+                cfg.profiles.insert(DEFAULT_PROFILE.to_string(), legacy);
+            }
+        }
+        Ok(cfg)
+    }
+
+    /// Loads secrets of profiles, which may ask for the passphrase.
+    pub fn unlock(&mut self) -> Result<(), Error> {
+        Ok(self.load_secrets()?)
+    }
+
+    fn load_secrets(&mut self) -> Result<(), secrets::Error> {
+        self.unlocked = true;
+        if let Some(secrets) = self
+            .secrets
+            .load::<BTreeMap<String, Profile>>(&mut self.passphrase, self.interactive)?
+        {
+            for (name, s) in secrets {
+                self.profiles.entry(name).or_default().set_secrets(s);
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves secrets to another storage. Secrets already in the new storage take precedence.
+    pub fn set_storage(&mut self, storage: Storage) -> Result<(), Error> {
+        self.secrets = storage;
+        self.passphrase = None;
+        match self.load_secrets() {
+            Err(secrets::Error::IO(e)) if e.kind() == ErrorKind::NotFound => {}
+            r => r?,
+        }
+        self.store()
+    }
+
+    pub fn store(&mut self) -> Result<(), Error> {
+        if self.secrets == Storage::Plain {
+            return Ok(confy::store(APP_NAME, &*self)?);
+        }
+        if !self.unlocked {
+            self.load_secrets()?;
+        }
+
+        self.secrets
+            .store(&self.profiles, &mut self.passphrase, self.interactive)?;
+        confy::store(
+            APP_NAME,
+            Self {
+                default_profile: self.default_profile.clone(),
+                secrets: self.secrets.clone(),
+                defaults: self.defaults.clone(),
+                presets: self.presets.clone(),
+                profiles: self
+                    .profiles
+                    .iter()
+                    .map(|(name, p)| {
+                        (
+                            name.clone(),
+                            Profile {
+                                init: p.init,
+                                ..Profile::default()
+                            },
+                        )
+                    })
+                    .collect(),
+                passphrase: None,
+                interactive: false,
+                unlocked: false,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Returns defaults overridden by the named preset.
+    pub fn preset(&self, name: Option<&str>) -> Result<Preset, Error> {
+        let preset = match name {
+            Some(name) => self
+                .presets
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::PresetNotFound(name.to_string()))?,
+            None => Preset::default(),
+        };
+        Ok(self.defaults.clone().merge(preset))
+    }
+
+    pub fn profile_name<'a>(&'a self, name: Option<&'a str>) -> &'a str {
+        name.or(self.default_profile.as_deref())
+            .unwrap_or(DEFAULT_PROFILE)
+    }
+
+    /// Returns other valid profiles following `name`, which are used in turn when rate limited.
+    pub fn rotation(&self, name: &str) -> Vec<(String, Profile)> {
+        let (before, after): (Vec<_>, Vec<_>) = self
+            .profiles
+            .iter()
+            .filter(|(n, p)| n.as_str() != name && p.is_valid())
+            .partition(|(n, _)| n.as_str() < name);
+        after
+            .into_iter()
+            .chain(before)
+            .map(|(n, p)| (n.to_string(), p.clone()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub authorization_token: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub csrf_token: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub cookie_auth_token: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub cookie_twitter_sess: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub cookie_ct0: String,
     pub init: bool,
 }
 
-impl Config {
-    pub fn from_cookie(
-        authorization_token: String,
-        csrf_token: String,
-        cookie: String,
-    ) -> Result<Self, CookieError> {
-        let cookie = cookie.parse::<Cookie>()?;
-
-        Ok(Self {
+impl Profile {
+    /// The CSRF token is always the same as the ct0 cookie.
+    pub fn from_cookie(authorization_token: String, cookie: Cookie) -> Self {
+        Self {
             authorization_token,
-            csrf_token,
+            csrf_token: cookie.ct0.clone(),
             cookie_auth_token: cookie.auth_token,
             cookie_twitter_sess: cookie.twitter_sess,
             cookie_ct0: cookie.ct0,
             init: true,
-        })
+        }
     }
 
-    pub fn load() -> Result<Self, confy::ConfyError> {
-        confy::load(APP_NAME)
+    fn set_secrets(&mut self, p: Profile) {
+        self.authorization_token = p.authorization_token;
+        self.csrf_token = p.csrf_token;
+        self.cookie_auth_token = p.cookie_auth_token;
+        self.cookie_twitter_sess = p.cookie_twitter_sess;
+        self.cookie_ct0 = p.cookie_ct0;
+        self.init = self.init || p.init;
     }
 
-    pub fn store(&self) -> Result<(), confy::ConfyError> {
-        confy::store(APP_NAME, self)
+    pub fn is_valid(&self) -> bool {
+        self.init && self.validate()
     }
 
     pub fn validate(&self) -> bool {
@@ -48,7 +210,7 @@ impl Config {
     }
 }
 
-impl Into<Auth> for Config {
+impl Into<Auth> for Profile {
     fn into(self) -> Auth {
         Auth {
             authorization_token: self.authorization_token,
@@ -61,3 +223,50 @@ impl Into<Auth> for Config {
         }
     }
 }
+
+/// Auth information given by environment variables or a cookie file, which takes precedence over profiles.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    authorization_token: Option<String>,
+    csrf_token: Option<String>,
+    cookie: Option<Cookie>,
+}
+
+impl Overrides {
+    pub fn from_env(cookie: Option<Cookie>) -> Result<Self, CookieError> {
+        let cookie = match cookie {
+            Some(c) => Some(c),
+            None => env::var(COOKIE_ENV).ok().map(|c| c.parse()).transpose()?,
+        };
+        Ok(Self {
+            authorization_token: env::var(BEARER_ENV).ok(),
+            csrf_token: env::var(CSRF_ENV).ok(),
+            cookie,
+        })
+    }
+
+    /// Returns a profile when the overrides are enough by themselves.
+    pub fn profile(&self) -> Option<Profile> {
+        if self.authorization_token.is_none() || self.cookie.is_none() {
+            return None;
+        }
+        Some(self.apply(Profile::default()))
+    }
+
+    pub fn apply(&self, mut p: Profile) -> Profile {
+        if let Some(t) = self.authorization_token.as_ref() {
+            p.authorization_token = t.clone();
+        }
+        if let Some(c) = self.cookie.as_ref() {
+            p.csrf_token = c.ct0.clone();
+            p.cookie_auth_token = c.auth_token.clone();
+            p.cookie_twitter_sess = c.twitter_sess.clone();
+            p.cookie_ct0 = c.ct0.clone();
+            p.init = true;
+        }
+        if let Some(t) = self.csrf_token.as_ref() {
+            p.csrf_token = t.clone();
+        }
+        p
+    }
+}
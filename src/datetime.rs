@@ -0,0 +1,62 @@
+use chrono::{DateTime, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use thiserror::Error;
+
+/// Format of `since:` and `until:` in search queries with time.
+pub const QUERY_FORMAT: &str = "%Y-%m-%d_%H:%M:%S_UTC";
+
+const FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+];
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid date or time: {0}")]
+    Invalid(String),
+    #[error("time does not exist in {1}: {0}")]
+    NonExistent(String, Tz),
+}
+
+/// Parses RFC 3339, a date and time like `2020-08-01 23:00` or a date like `2020-08-01`.
+/// Values without an offset are read in `tz`, and a date means its midnight.
+pub fn parse(s: &str, tz: Tz) -> Result<DateTime<Utc>, Error> {
+    if let Ok(d) = DateTime::parse_from_rfc3339(s) {
+        return Ok(d.with_timezone(&Utc));
+    }
+
+    let naive = FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .map(|d| d.and_hms(0, 0, 0))
+        })
+        .ok_or_else(|| Error::Invalid(s.to_string()))?;
+
+    match tz.from_local_datetime(&naive) {
+        LocalResult::Single(d) | LocalResult::Ambiguous(d, _) => Ok(d.with_timezone(&Utc)),
+        LocalResult::None => Err(Error::NonExistent(s.to_string(), tz)),
+    }
+}
+
+#[test]
+fn test_parse() {
+    let tokyo = chrono_tz::Asia::Tokyo;
+    assert_eq!(
+        Utc.ymd(2020, 7, 31).and_hms(15, 0, 0),
+        parse("2020-08-01", tokyo).unwrap()
+    );
+    assert_eq!(
+        Utc.ymd(2020, 8, 1).and_hms(14, 30, 0),
+        parse("2020-08-01 23:30", tokyo).unwrap()
+    );
+    assert_eq!(
+        Utc.ymd(2020, 8, 1).and_hms(23, 30, 0),
+        parse("2020-08-01T23:30:00Z", tokyo).unwrap()
+    );
+    assert!(parse("2020-08-01 25:00", tokyo).is_err());
+}
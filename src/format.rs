@@ -0,0 +1,51 @@
+use crate::MainResult;
+use serde::{Deserialize, Serialize};
+use std::{io::Write, str::FromStr};
+use twinicodo::nicodo::{write_json, write_nvcomment, write_xml, Chat, ThreadInfo};
+
+/// Formats of output comment files.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// niconico XML with `packet`
+    #[default]
+    Xml,
+    /// Legacy JSON array of `thread` and `chat`
+    Json,
+    /// JSON of the nvcomment API
+    Nvcomment,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "xml" => Ok(Format::Xml),
+            "json" => Ok(Format::Json),
+            "nvcomment" => Ok(Format::Nvcomment),
+            _ => Err(format!(
+                "unknown format, expected xml, json or nvcomment: {}",
+                s
+            )),
+        }
+    }
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Xml => "xml",
+            Format::Json | Format::Nvcomment => "json",
+        }
+    }
+
+    pub fn write<W: Write>(self, writer: W, chats: &[Chat], info: &ThreadInfo) -> MainResult<()> {
+        match self {
+            Format::Xml => write_xml(writer, chats, info)?,
+            Format::Json => write_json(writer, chats, info)?,
+            Format::Nvcomment => write_nvcomment(writer, chats, info)?,
+        }
+        Ok(())
+    }
+}
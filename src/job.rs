@@ -0,0 +1,229 @@
+use crate::{
+    datetime::{self, QUERY_FORMAT},
+    preset::Preset,
+    progress::{Mode, Progress},
+    template::{Vars, DEFAULT_FILENAME},
+    MainResult,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use futures::stream::TryStreamExt;
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, File},
+    io::BufWriter,
+    path::PathBuf,
+};
+use tokio::task::spawn_blocking;
+use tracing::{debug, info};
+use twinicodo::{
+    anonymize::AnonymizeChats,
+    filter::FilterChats,
+    iter::SortedTweetToChat,
+    twitter::{Query, Tweet, TwitterClient, MAX_QUERY_LENGTH},
+};
+
+/// A search and its conversion into a comment file.
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// Queries searched in turn, whose results are combined
+    pub queries: Vec<String>,
+    /// Start of the search in a format read by `datetime::parse`
+    pub since: String,
+    /// Exclusive end of the search
+    pub until: String,
+    /// Time at vpos 0. The first tweet is used when not specified.
+    pub anchor: Option<String>,
+    pub episode: Option<u32>,
+    pub preset: Preset,
+    /// Output filename template. The preset or the default is used when not specified.
+    pub output: Option<String>,
+    /// JSON file where searched tweets are saved
+    pub archive: Option<PathBuf>,
+}
+
+/// Times of a job in UTC.
+#[derive(Debug)]
+struct Window {
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    anchor: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub struct Outcome {
+    /// `None` when no tweet is found
+    pub output: Option<PathBuf>,
+    pub count: usize,
+    /// Number of tweets outside the time window
+    pub trimmed: usize,
+}
+
+impl Job {
+    fn template(&self) -> &str {
+        self.output
+            .as_deref()
+            .or(self.preset.filename.as_deref())
+            .unwrap_or(DEFAULT_FILENAME)
+    }
+
+    /// Reads times in the timezone of the preset, which defaults to UTC.
+    fn window(&self) -> MainResult<Window> {
+        let tz = self.preset.timezone.unwrap_or(Tz::UTC);
+        Ok(Window {
+            since: datetime::parse(&self.since, tz)?,
+            until: datetime::parse(&self.until, tz)?,
+            anchor: self
+                .anchor
+                .as_ref()
+                .map(|a| datetime::parse(a, tz))
+                .transpose()?,
+        })
+    }
+
+    /// Fails when the job is broken, which is better to know before searching.
+    pub fn validate(&self) -> MainResult<()> {
+        self.window()?;
+        Vars::default().render(self.template())?;
+        Ok(())
+    }
+
+    /// `label` is shown with the progress to tell jobs apart.
+    pub async fn run(
+        &self,
+        client: &TwitterClient,
+        mode: Mode,
+        label: Option<String>,
+    ) -> MainResult<Outcome> {
+        self.validate()?;
+        let window = self.window()?;
+        info!(
+            queries = ?self.queries,
+            since = %window.since,
+            until = %window.until,
+            "running a job"
+        );
+
+        // long OR-lists are searched in several requests
+        let searches = self
+            .queries
+            .iter()
+            .flat_map(|q| {
+                Query {
+                    text: q.to_string(),
+                    since: Some(window.since.format(QUERY_FORMAT).to_string()),
+                    until: Some(window.until.format(QUERY_FORMAT).to_string()),
+                }
+                .split(MAX_QUERY_LENGTH)
+                .into_iter()
+                .map(move |s| (q, s))
+            })
+            .collect::<Vec<_>>();
+
+        // TODO: write json async, and converting into XML will be executed after writing JSON
+        let mut tweets = Vec::<Tweet>::new();
+        let mut indices = HashMap::new();
+        for (i, (q, search)) in searches.iter().enumerate() {
+            let label = match (label.as_ref(), searches.len()) {
+                (l, 1) => l.cloned(),
+                (l, n) => Some(format!(
+                    "{}[{}/{}]",
+                    l.map(|l| l.as_str()).unwrap_or("query"),
+                    i + 1,
+                    n
+                )),
+            };
+            let mut progress = Progress::new(mode, label, window.since, window.until);
+            let pages = client
+                .search_tweets(search.clone())
+                .inspect_ok(|r| progress.page(r))
+                .try_collect::<Vec<_>>()
+                .await?;
+            progress.finish();
+
+            // a tweet found by several queries is kept once with all of them
+            for t in pages.into_iter().flatten() {
+                let i = *indices.entry(t.id.clone()).or_insert_with(|| {
+                    tweets.push(t);
+                    tweets.len() - 1
+                });
+                if !tweets[i].queries.contains(q) {
+                    tweets[i].queries.push(q.to_string());
+                }
+            }
+        }
+
+        // search results may include tweets slightly outside the window
+        let (tweets, outside): (Vec<_>, Vec<_>) = tweets
+            .into_iter()
+            .partition(|t| t.is_within(window.since, window.until));
+        let trimmed = outside.len();
+        debug!(
+            tweets = tweets.len(),
+            trimmed, "trimmed tweets outside the window"
+        );
+
+        if let Some(path) = self.archive.clone() {
+            let tweets = tweets.clone();
+            spawn_blocking(move || -> MainResult<()> {
+                if let Some(dir) = path.parent() {
+                    create_dir_all(dir)?;
+                }
+                serde_json::to_writer(BufWriter::new(File::create(path)?), &tweets)?;
+                Ok(())
+            })
+            .await??;
+        }
+
+        let chats = tweets
+            .into_iter()
+            .map_to_sorted_chats_with(self.preset.convert_options(window.anchor))
+            .filter_chats(self.preset.filter())
+            .anonymize(self.preset.privacy())
+            .collect::<Vec<_>>();
+
+        if chats.is_empty() {
+            return Ok(Outcome {
+                output: None,
+                count: 0,
+                trimmed,
+            });
+        }
+
+        let format = self.preset.format.unwrap_or_default();
+        let anchor = window
+            .anchor
+            .or_else(|| chats.first().map(|c| Utc.timestamp(c.date as i64, 0)));
+        let info = self.preset.thread_info(anchor.map(|a| a.timestamp()));
+        let output = self.preset.output_dir.clone().unwrap_or_default().join(
+            Vars {
+                query: &self.queries.join(" "),
+                since: &self.since,
+                until: &self.until,
+                count: chats.len(),
+                format: format.extension(),
+                episode: self.episode,
+                date: anchor.map(|d| d.with_timezone(&self.preset.timezone.unwrap_or(Tz::UTC))),
+            }
+            .render(self.template())?,
+        );
+
+        let count = chats.len();
+        let path = output.clone();
+        spawn_blocking(move || -> MainResult<()> {
+            if let Some(dir) = path.parent() {
+                create_dir_all(dir)?;
+            }
+            let w = BufWriter::new(File::create(path)?);
+            format.write(w, &chats, &info)?;
+            Ok(())
+        })
+        .await??;
+
+        Ok(Outcome {
+            output: Some(output),
+            count,
+            trimmed,
+        })
+    }
+}
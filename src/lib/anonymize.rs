@@ -0,0 +1,104 @@
+use super::nicodo::Chat;
+use sha2::{Digest, Sha256};
+
+/// How user and tweet IDs are published.
+#[derive(Debug, Clone, Default)]
+pub enum Privacy {
+    #[default]
+    Keep,
+    /// Replaces user IDs with opaque IDs hashed with the salt, which are stable within a file,
+    /// and removes tweet IDs.
+    Anonymize { salt: String },
+    /// Removes user and tweet IDs.
+    Strip,
+}
+
+/// Makes an opaque ID of 27 characters like niconico's anonymous user IDs.
+fn opaque_id(salt: &str, user_id: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(b"\0");
+    hasher.update(user_id.as_bytes());
+    base64::encode_config(&hasher.finalize()[..20], base64::URL_SAFE_NO_PAD)
+}
+
+pub struct AnonymizeIterator<I>
+where
+    I: Iterator<Item = Chat>,
+{
+    iter: I,
+    privacy: Privacy,
+}
+
+impl<I> Iterator for AnonymizeIterator<I>
+where
+    I: Iterator<Item = Chat>,
+{
+    type Item = Chat;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let c = self.iter.next()?;
+        Some(match &self.privacy {
+            Privacy::Keep => c,
+            Privacy::Anonymize { salt } => Chat {
+                user_id: c.user_id.as_ref().map(|u| opaque_id(salt, u)),
+                anonymity: c.user_id.as_ref().map(|_| 1).or(c.anonymity),
+                id: None,
+                ..c
+            },
+            Privacy::Strip => Chat {
+                user_id: None,
+                id: None,
+                ..c
+            },
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+pub trait AnonymizeChats: Iterator<Item = Chat> + Sized {
+    fn anonymize(self, privacy: Privacy) -> AnonymizeIterator<Self>;
+}
+
+impl<I> AnonymizeChats for I
+where
+    I: Iterator<Item = Chat>,
+{
+    fn anonymize(self, privacy: Privacy) -> AnonymizeIterator<Self> {
+        AnonymizeIterator {
+            iter: self,
+            privacy,
+        }
+    }
+}
+
+#[test]
+fn test_anonymize() {
+    let chat = |user: &str| Chat {
+        user_id: Some(user.to_string()),
+        id: Some("1".to_string()),
+        content: "a".to_string(),
+        ..Chat::default()
+    };
+    let privacy = Privacy::Anonymize {
+        salt: "salt".to_string(),
+    };
+    let chats = vec![chat("a"), chat("a"), chat("b")]
+        .into_iter()
+        .anonymize(privacy)
+        .collect::<Vec<_>>();
+    assert_eq!(chats[0].user_id, chats[1].user_id);
+    assert_ne!(chats[0].user_id, chats[2].user_id);
+    assert_eq!(27, chats[0].user_id.as_ref().unwrap().len());
+    assert_eq!(None, chats[0].id);
+    assert_eq!(Some(1), chats[0].anonymity);
+
+    let chats = vec![chat("a")]
+        .into_iter()
+        .anonymize(Privacy::Strip)
+        .collect::<Vec<_>>();
+    assert_eq!(None, chats[0].user_id);
+}
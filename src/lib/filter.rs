@@ -0,0 +1,126 @@
+use super::nicodo::Chat;
+use std::collections::VecDeque;
+use tracing::trace;
+
+/// Rules to drop chats after conversion. Chats are expected to be sorted by vpos.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Chats containing any of them are dropped, ignoring case.
+    pub ng_words: Vec<String>,
+    /// Chats from these users are dropped.
+    pub ng_users: Vec<String>,
+    /// Maximum number of chats within a second. Owner comments are not counted.
+    pub density: Option<usize>,
+}
+
+impl Filter {
+    fn accepts(&self, c: &Chat) -> bool {
+        let content = c.content.to_lowercase();
+        !self
+            .ng_words
+            .iter()
+            .any(|w| content.contains(&w.to_lowercase()))
+            && !c
+                .user_id
+                .as_ref()
+                .map(|u| self.ng_users.iter().any(|n| n.eq_ignore_ascii_case(u)))
+                .unwrap_or(false)
+    }
+}
+
+pub struct FilterIterator<I>
+where
+    I: Iterator<Item = Chat>,
+{
+    iter: I,
+    filter: Filter,
+    recent: VecDeque<u64>,
+}
+
+impl<I> Iterator for FilterIterator<I>
+where
+    I: Iterator<Item = Chat>,
+{
+    type Item = Chat;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for c in &mut self.iter {
+            if !self.filter.accepts(&c) {
+                trace!(id = ?c.id, "dropped a chat by NG rules");
+                continue;
+            }
+
+            if c.fork == Some(1) {
+                return Some(c);
+            }
+
+            if let Some(density) = self.filter.density {
+                while let Some(&v) = self.recent.front() {
+                    if v + 100 > c.vpos {
+                        break;
+                    }
+                    self.recent.pop_front();
+                }
+                if self.recent.len() >= density {
+                    trace!(id = ?c.id, vpos = c.vpos, "dropped a chat by density");
+                    continue;
+                }
+                self.recent.push_back(c.vpos);
+            }
+
+            return Some(c);
+        }
+        None
+    }
+}
+
+pub trait FilterChats: Iterator<Item = Chat> + Sized {
+    fn filter_chats(self, filter: Filter) -> FilterIterator<Self>;
+}
+
+impl<I> FilterChats for I
+where
+    I: Iterator<Item = Chat>,
+{
+    fn filter_chats(self, filter: Filter) -> FilterIterator<Self> {
+        FilterIterator {
+            iter: self,
+            filter,
+            recent: VecDeque::new(),
+        }
+    }
+}
+
+#[test]
+fn test_filter_chats() {
+    let chat = |vpos: u64, user: &str, content: &str| Chat {
+        date: 0,
+        vpos,
+        user_id: Some(user.to_string()),
+        id: None,
+        mail: None,
+        content: content.to_string(),
+        ..Chat::default()
+    };
+    let chats = vec![
+        chat(0, "a", "1"),
+        chat(10, "b", "2"),
+        chat(20, "c", "3"),
+        chat(50, "ng", "4"),
+        chat(110, "d", "Spoiler"),
+        chat(110, "e", "5"),
+    ];
+    let filter = Filter {
+        ng_words: vec!["spoiler".to_string()],
+        ng_users: vec!["NG".to_string()],
+        density: Some(2),
+    };
+    assert_eq!(
+        vec!["1", "2", "5"],
+        chats
+            .into_iter()
+            .filter_chats(filter)
+            .map(|c| c.content)
+            .collect::<Vec<_>>()
+    );
+}
@@ -1,12 +1,183 @@
-use super::{nicodo::Chat, twitter::Tweet};
+use super::{
+    lang::LangFilter,
+    nicodo::Chat,
+    twitter::{Tweet, User},
+};
+use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
-use regex::Regex;
+use regex::{Captures, Regex};
+use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 use std::vec::IntoIter as VecIntoIter;
+use tracing::{debug, trace};
 
-pub struct TweetToChatIterator<I>(I, u64)
+/// Options to convert tweets into chats.
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Time at vpos 0. The first tweet is used when not specified.
+    pub anchor: Option<DateTime<Utc>>,
+    /// Seconds added to vpos of all chats. Chats which get negative vpos are dropped.
+    pub offset: i64,
+    pub cleanup: Cleanup,
+    pub extras: Extras,
+    pub threading: Threading,
+    /// Tweets in any languages are kept as they are when not specified.
+    pub languages: Option<LangFilter>,
+    /// Screen names whose tweets become owner comments, ignoring case.
+    pub owners: Vec<String>,
+    pub attribution: Option<Attribution>,
+}
+
+/// Shows authors in comments with a template such as `{text} ({name})` or `@{screen_name} {text}`.
+#[derive(Debug, Clone)]
+pub struct Attribution {
+    pub template: String,
+    /// Only tweets from these screen names are attributed, ignoring case. All tweets are when empty.
+    pub accounts: Vec<String>,
+}
+
+impl Attribution {
+    fn apply(&self, text: &str, user: Option<&User>) -> String {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"\{(text|name|screen_name)\}").unwrap();
+        }
+
+        let user = match user {
+            Some(u)
+                if self.accounts.is_empty()
+                    || self
+                        .accounts
+                        .iter()
+                        .any(|a| a.eq_ignore_ascii_case(&u.screen_name)) =>
+            {
+                u
+            }
+            _ => return text.to_string(),
+        };
+        RE.replace_all(&self.template, |c: &Captures| match &c[1] {
+            "name" => user.name.to_string(),
+            "screen_name" => user.screen_name.to_string(),
+            _ => text.to_string(),
+        })
+        .into_owned()
+    }
+}
+
+/// What is removed from tweet texts.
+#[derive(Debug, Clone)]
+pub struct Cleanup {
+    pub hashtags: bool,
+    pub urls: bool,
+    pub mentions: bool,
+}
+
+impl Default for Cleanup {
+    fn default() -> Self {
+        Self {
+            hashtags: true,
+            urls: true,
+            mentions: false,
+        }
+    }
+}
+
+/// What is added to tweet texts from other parts of tweets.
+#[derive(Debug, Clone, Default)]
+pub struct Extras {
+    /// Placeholders like `[画像]` for attached media
+    pub media: bool,
+    /// Alt text of attached images
+    pub alt_text: bool,
+    /// Text of quoted tweets
+    pub quoted: bool,
+    /// Titles of link cards
+    pub cards: bool,
+}
+
+impl Extras {
+    fn apply(&self, text: String, t: &Tweet, c: &Cleanup) -> String {
+        let mut parts = vec![text];
+
+        for m in &t.entities.media {
+            let alt = m
+                .ext_alt_text
+                .as_deref()
+                .filter(|_| self.alt_text)
+                .map(str::trim)
+                .filter(|a| !a.is_empty());
+            let kind = match m.kind.as_str() {
+                "photo" => "画像",
+                _ => "動画",
+            };
+            parts.push(match (self.media, alt) {
+                (true, Some(alt)) => format!("[{}: {}]", kind, alt),
+                (true, None) => format!("[{}]", kind),
+                (false, Some(alt)) => alt.to_string(),
+                (false, None) => continue,
+            });
+        }
+
+        if self.quoted {
+            if let Some(q) = t.quoted_status.as_ref() {
+                parts.push(format!("[引用] {}", cleanup(&q.full_text, c)));
+            }
+        }
+        if self.cards {
+            if let Some(title) = t
+                .extra
+                .get("card")
+                .and_then(|card| card["binding_values"]["title"]["string_value"].as_str())
+            {
+                parts.push(format!("[リンク] {}", title.trim()));
+            }
+        }
+
+        parts.retain(|p| !p.is_empty());
+        parts.join(" ")
+    }
+}
+
+/// How replies are grouped with their parent. Parents are looked up only within the tweets
+/// converted before, so tweets should be sorted.
+#[derive(Debug, Clone, Default)]
+pub struct Threading {
+    /// Drops replies whose parent is not in the tweets
+    pub drop_orphans: bool,
+    /// Prefixes replies with the parent text like `> parent` up to this number of characters
+    pub quote_parent: Option<usize>,
+    /// Places replies just after their parent in vpos
+    pub follow_parent: bool,
+}
+
+impl Threading {
+    fn is_enabled(&self) -> bool {
+        self.drop_orphans || self.quote_parent.is_some() || self.follow_parent
+    }
+}
+
+/// Tweet which replies may refer to.
+#[derive(Debug, Clone)]
+struct Parent {
+    content: String,
+    /// `None` when the tweet is dropped before vpos 0
+    vpos: Option<u64>,
+}
+
+/// Converts tweets into chats.
+///
+/// This is not an `ExactSizeIterator` since tweets before vpos 0, in other languages or
+/// replying to unknown tweets may be dropped. `size_hint` gives the number of remaining
+/// tweets as the upper bound; collect chats first to know how many there are.
+pub struct TweetToChatIterator<I>
 where
-    I: ExactSizeIterator<Item = Tweet>;
+    I: ExactSizeIterator<Item = Tweet>,
+{
+    iter: I,
+    anchor: Option<i64>,
+    options: ConvertOptions,
+    parents: HashMap<String, Parent>,
+    last_vpos: u64,
+}
 
 impl<I> Iterator for TweetToChatIterator<I>
 where
@@ -15,40 +186,120 @@ where
     type Item = Chat;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|t| {
-            let date = t.created_at.map(|d| d.timestamp() as u64).unwrap_or(0);
-            let vpos = if self.1 == 0 {
-                0
-            } else {
-                (date - self.1) * 100
-            };
-            if self.1 == 0 {
-                self.1 = date;
+        for t in &mut self.iter {
+            let mut mail = None;
+            if let Some(l) = self.options.languages.as_ref() {
+                if !l.accepts(&t) {
+                    if l.mail.is_none() {
+                        trace!(id = %t.id, lang = ?t.lang, "dropped a tweet in another language");
+                        continue;
+                    }
+                    mail = l.mail.clone();
+                }
+            }
+
+            let threading = &self.options.threading;
+            let parents = &self.parents;
+            // `Some(None)` for orphan replies
+            let parent = t
+                .in_reply_to_status_id
+                .as_ref()
+                .map(|id| parents.get(id).cloned());
+            if threading.drop_orphans {
+                if let Some(None) = parent {
+                    trace!(id = %t.id, "dropped an orphan reply");
+                    continue;
+                }
+            }
+            let parent = parent.flatten();
+
+            let date = t.created_at.map(|d| d.timestamp()).unwrap_or(0);
+            let date_usec = t.created_at.map(|d| d.timestamp_subsec_micros());
+            let anchor = *self.anchor.get_or_insert_with(|| {
+                debug!(anchor = date, "anchored at the first tweet");
+                date
+            });
+            let mut content = self.options.extras.apply(
+                cleanup(&t.full_text, &self.options.cleanup),
+                &t,
+                &self.options.cleanup,
+            );
+            let vpos = (date - anchor + self.options.offset) * 100;
+            if vpos < 0 {
+                trace!(id = %t.id, vpos, "dropped a tweet before vpos 0");
+                if threading.is_enabled() {
+                    self.parents.insert(
+                        t.id,
+                        Parent {
+                            content,
+                            vpos: None,
+                        },
+                    );
+                }
+                continue;
+            }
+            let mut vpos = vpos as u64;
+            if threading.follow_parent {
+                vpos = match parent.as_ref().and_then(|p| p.vpos) {
+                    Some(p) => p.max(self.last_vpos) + 1,
+                    None => vpos.max(self.last_vpos),
+                };
+            }
+            self.last_vpos = vpos;
+
+            if threading.is_enabled() {
+                let p = Parent {
+                    content: content.clone(),
+                    vpos: Some(vpos),
+                };
+                self.parents.insert(t.id.clone(), p);
+            }
+            let parent = parent.filter(|p| !p.content.is_empty());
+            if let (Some(n), Some(p)) = (threading.quote_parent, parent) {
+                let mut quote = p.content.chars().take(n).collect::<String>();
+                if p.content.chars().nth(n).is_some() {
+                    quote.push('…');
+                }
+                content = format!("> {} {}", quote, content);
+            }
+            if let Some(a) = self.options.attribution.as_ref() {
+                content = a.apply(&content, t.user.as_ref());
             }
 
-            Self::Item {
+            let user_id = t.user.map(|u| u.screen_name);
+            let owner = user_id
+                .as_ref()
+                .map(|u| {
+                    self.options
+                        .owners
+                        .iter()
+                        .any(|o| o.eq_ignore_ascii_case(u))
+                })
+                .unwrap_or(false);
+
+            return Some(Self::Item {
                 vpos,
-                date,
+                date: date as u64,
+                date_usec,
                 id: Some(t.id),
-                user_id: t.user.map(|u| u.screen_name),
-                mail: None,
-                content: cleanup(&t.full_text),
-            }
-        })
+                user_id,
+                mail,
+                content,
+                fork: if owner { Some(1) } else { None },
+                ..Self::Item::default()
+            });
+        }
+        None
     }
-}
 
-impl<I> ExactSizeIterator for TweetToChatIterator<I>
-where
-    I: ExactSizeIterator<Item = Tweet>,
-{
-    fn len(&self) -> usize {
-        self.0.len()
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.iter.len()))
     }
 }
 
 pub trait TweetToChat<I: ExactSizeIterator<Item = Tweet>>: ExactSizeIterator<Item = Tweet> {
     fn map_to_chat(self) -> TweetToChatIterator<I>;
+    fn map_to_chat_with(self, options: ConvertOptions) -> TweetToChatIterator<I>;
 }
 
 impl<I> TweetToChat<I> for I
@@ -56,26 +307,48 @@ where
     I: ExactSizeIterator<Item = Tweet>,
 {
     fn map_to_chat(self) -> TweetToChatIterator<Self> {
-        TweetToChatIterator(self, 0)
+        self.map_to_chat_with(ConvertOptions::default())
+    }
+
+    fn map_to_chat_with(self, options: ConvertOptions) -> TweetToChatIterator<Self> {
+        TweetToChatIterator {
+            iter: self,
+            anchor: options.anchor.map(|d| d.timestamp()),
+            options,
+            parents: HashMap::new(),
+            last_vpos: 0,
+        }
     }
 }
 
-fn cleanup(s: &str) -> String {
+fn cleanup(s: &str, c: &Cleanup) -> String {
     lazy_static! {
         static ref RE_HASHTAG: Regex = Regex::new(r"#[\w_]+[ \t]*").unwrap();
         static ref RE_URL: Regex = Regex::new(r"(?:https?|ftp)://[\n\S]+").unwrap();
+        static ref RE_MENTION: Regex = Regex::new(r"@\w+[ \t]*").unwrap();
     }
 
-    RE_URL
-        .replace_all(&RE_HASHTAG.replace_all(s, "").trim(), "")
-        .trim()
-        .to_string()
+    let mut s = s.trim().to_string();
+    for (enabled, re) in &[
+        (c.hashtags, &*RE_HASHTAG),
+        (c.urls, &*RE_URL),
+        (c.mentions, &*RE_MENTION),
+    ] {
+        if *enabled {
+            s = re.replace_all(&s, "").trim().to_string();
+        }
+    }
+    s
 }
 
 pub trait SortedTweetToChat<I: ExactSizeIterator<Item = Tweet>>:
     ExactSizeIterator<Item = Tweet>
 {
     fn map_to_sorted_chats(self) -> TweetToChatIterator<VecIntoIter<Tweet>>;
+    fn map_to_sorted_chats_with(
+        self,
+        options: ConvertOptions,
+    ) -> TweetToChatIterator<VecIntoIter<Tweet>>;
 }
 
 impl<I> SortedTweetToChat<I> for I
@@ -83,8 +356,181 @@ where
     I: ExactSizeIterator<Item = Tweet>,
 {
     fn map_to_sorted_chats(self) -> TweetToChatIterator<VecIntoIter<Tweet>> {
+        self.map_to_sorted_chats_with(ConvertOptions::default())
+    }
+
+    fn map_to_sorted_chats_with(
+        self,
+        options: ConvertOptions,
+    ) -> TweetToChatIterator<VecIntoIter<Tweet>> {
         let mut v = Vec::from_iter(self);
         v.sort_by(|a, b| a.created_at.cmp(&b.created_at));
-        v.into_iter().map_to_chat()
+        if options.threading.follow_parent {
+            let date = |t: &Tweet| t.created_at.map(|d| d.timestamp()).unwrap_or(0);
+            let anchor = options
+                .anchor
+                .map(|d| d.timestamp())
+                .or_else(|| v.first().map(date))
+                .unwrap_or(0);
+            v = follow_parents(v, |t| date(t) - anchor + options.offset < 0);
+        }
+        v.into_iter().map_to_chat_with(options)
+    }
+}
+
+/// Moves replies just after their parent and its earlier replies, keeping the order otherwise.
+/// Replies to tweets dropped before vpos 0 stay at their own time.
+fn follow_parents<F>(tweets: Vec<Tweet>, dropped: F) -> Vec<Tweet>
+where
+    F: Fn(&Tweet) -> bool,
+{
+    let ids = tweets
+        .iter()
+        .filter(|t| !dropped(t))
+        .map(|t| t.id.clone())
+        .collect::<HashSet<_>>();
+    let mut roots = Vec::new();
+    let mut replies = HashMap::<String, Vec<Tweet>>::new();
+    for t in tweets {
+        match t.in_reply_to_status_id.clone() {
+            Some(p) if ids.contains(&p) => replies.entry(p).or_default().push(t),
+            _ => roots.push(t),
+        }
+    }
+
+    let mut ordered = Vec::new();
+    let mut stack = roots.into_iter().rev().collect::<Vec<_>>();
+    while let Some(t) = stack.pop() {
+        if let Some(r) = replies.remove(&t.id) {
+            stack.extend(r.into_iter().rev());
+        }
+        ordered.push(t);
     }
+    ordered
+}
+
+#[test]
+fn test_attribution() {
+    let user = |name: &str, screen_name: &str| User {
+        name: name.to_string(),
+        screen_name: screen_name.to_string(),
+        ..User::default()
+    };
+    let attribution = Attribution {
+        template: "{text} ({name})".to_string(),
+        accounts: vec!["Cast".to_string()],
+    };
+    assert_eq!(
+        "hello ({text})",
+        attribution.apply("hello", Some(&user("{text}", "cast")))
+    );
+    assert_eq!("hello", attribution.apply("hello", Some(&user("B", "b"))));
+    assert_eq!("hello", attribution.apply("hello", None));
+}
+
+#[test]
+fn test_extras() {
+    let tweet = Tweet {
+        full_text: "https://t.co/a".to_string(),
+        entities: serde_json::from_str(
+            r#"{"media": [
+                {"type": "photo", "ext_alt_text": "a cat"},
+                {"type": "video", "ext_alt_text": null}
+            ]}"#,
+        )
+        .unwrap(),
+        quoted_status: Some(Box::new(Tweet {
+            full_text: "quoted #tag".to_string(),
+            ..Tweet::default()
+        })),
+        ..Tweet::default()
+    };
+    let c = Cleanup::default();
+    let text = cleanup(&tweet.full_text, &c);
+
+    assert_eq!("", Extras::default().apply(text.clone(), &tweet, &c));
+    let extras = Extras {
+        media: true,
+        alt_text: true,
+        quoted: true,
+        cards: true,
+    };
+    assert_eq!(
+        "[画像: a cat] [動画] [引用] quoted",
+        extras.apply(text, &tweet, &c)
+    );
+}
+
+#[test]
+fn test_threading() {
+    use chrono::TimeZone;
+
+    let tweet = |id: &str, secs: i64, parent: Option<&str>| Tweet {
+        id: id.to_string(),
+        created_at: Some(Utc.timestamp(1596294000 + secs, 0)),
+        full_text: format!("tweet {}", id),
+        in_reply_to_status_id: parent.map(|p| p.to_string()),
+        ..Tweet::default()
+    };
+    let tweets = || {
+        vec![
+            tweet("1", 0, None),
+            tweet("2", 5, None),
+            tweet("3", 10, Some("1")),
+            tweet("4", 15, Some("0")),
+            tweet("5", 20, Some("4")),
+        ]
+    };
+    let convert = |threading| {
+        let options = ConvertOptions {
+            threading,
+            ..ConvertOptions::default()
+        };
+        tweets()
+            .into_iter()
+            .map_to_sorted_chats_with(options)
+            .map(|c| (c.vpos, c.content))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        vec![
+            (0, "tweet 1".to_string()),
+            (1, "> tweet… tweet 3".to_string()),
+            (500, "tweet 2".to_string()),
+        ],
+        convert(Threading {
+            drop_orphans: true,
+            quote_parent: Some(5),
+            follow_parent: true,
+        })
+    );
+    assert_eq!(5, convert(Threading::default()).len());
+
+    // the parent is dropped before the anchor but still quoted
+    let options = ConvertOptions {
+        anchor: Some(Utc.timestamp(1596294000, 0)),
+        threading: Threading {
+            drop_orphans: false,
+            quote_parent: Some(10),
+            follow_parent: true,
+        },
+        ..ConvertOptions::default()
+    };
+    let chats = vec![
+        tweet("1", -10, None),
+        tweet("2", 10, Some("1")),
+        tweet("3", 5, None),
+    ]
+    .into_iter()
+    .map_to_sorted_chats_with(options)
+    .map(|c| (c.vpos, c.content))
+    .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            (500, "tweet 3".to_string()),
+            (1000, "> tweet 1 tweet 2".to_string()),
+        ],
+        chats
+    );
 }
@@ -0,0 +1,208 @@
+use super::twitter::Tweet;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Codes of Twitter for tweets without a language, like ones with only media or hashtags.
+const UNDETERMINED: &[&str] = &["und", "qam", "qct", "qht", "qme", "qst", "zxx", "art"];
+
+/// Deprecated codes of Twitter and the current ones returned by `detect`.
+const ALIASES: &[(&str, &str)] = &[("in", "id"), ("iw", "he")];
+
+/// Common words and character n-grams of languages written in Latin script.
+const LATIN: &[(&str, &[&str], &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "is", "you", "this", "that", "for", "it", "of", "was",
+        ],
+        &["th", "ing", "ght"],
+    ),
+    (
+        "es",
+        &[
+            "el", "la", "que", "los", "es", "por", "muy", "pero", "una", "del",
+        ],
+        &["ñ", "ción", "¿", "¡"],
+    ),
+    (
+        "pt",
+        &[
+            "o", "que", "não", "os", "uma", "muito", "com", "mas", "você", "eu",
+        ],
+        &["ão", "ç", "õe", "nh"],
+    ),
+    (
+        "fr",
+        &[
+            "le", "les", "est", "et", "je", "pas", "une", "des", "c'est", "trop",
+        ],
+        &["eau", "è", "ê", "qu"],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "und", "ist", "ich", "nicht", "das", "ein", "sehr", "mit",
+        ],
+        &["ß", "ä", "ö", "ü", "sch"],
+    ),
+    (
+        "id",
+        &[
+            "yang", "dan", "ini", "itu", "aku", "tidak", "ada", "banget", "sama", "juga",
+        ],
+        &["ng", "ny"],
+    ),
+];
+
+/// Which tweets are kept by their language.
+#[derive(Debug, Clone, Default)]
+pub struct LangFilter {
+    /// Codes like `ja`. Tweets of unknown languages are kept.
+    pub langs: Vec<String>,
+    /// Commands in `mail` for tweets in other languages, which are dropped when not specified.
+    pub mail: Option<String>,
+}
+
+impl LangFilter {
+    /// Whether the tweet is in one of the languages, or its language is unknown.
+    pub fn accepts(&self, t: &Tweet) -> bool {
+        match lang(t) {
+            Some(l) => self
+                .langs
+                .iter()
+                .any(|a| primary(a).eq_ignore_ascii_case(primary(&l))),
+            None => true,
+        }
+    }
+}
+
+/// Primary subtag of the code, replacing deprecated codes which Twitter still uses.
+fn primary(lang: &str) -> &str {
+    let p = lang.split('-').next().unwrap_or(lang);
+    ALIASES
+        .iter()
+        .find(|(old, _)| old.eq_ignore_ascii_case(p))
+        .map(|(_, new)| *new)
+        .unwrap_or(p)
+}
+
+/// Language of the tweet by Twitter, or detected from the text.
+pub fn lang(t: &Tweet) -> Option<String> {
+    t.lang
+        .as_deref()
+        .filter(|l| !UNDETERMINED.contains(l))
+        .map(|l| l.to_string())
+        .or_else(|| detect(&t.full_text).map(|l| l.to_string()))
+}
+
+/// Guesses the language by scripts, or by common words and n-grams for Latin script.
+/// Returns `None` for texts without enough letters.
+pub fn detect(text: &str) -> Option<&'static str> {
+    lazy_static! {
+        static ref RE_SKIP: Regex = Regex::new(r"(?:https?://|[#@])\S+").unwrap();
+    }
+
+    let text = RE_SKIP.replace_all(text, " ").to_lowercase();
+    let mut kana = 0;
+    let mut scripts = [
+        ("zh", 0), // Han
+        ("ko", 0),
+        ("ru", 0),
+        ("ar", 0),
+        ("th", 0),
+        ("he", 0),
+        ("el", 0),
+        ("hi", 0),
+        ("latin", 0),
+    ];
+    for c in text.chars() {
+        let i = match c {
+            '\u{3040}'..='\u{30ff}' | '\u{ff66}'..='\u{ff9f}' => {
+                kana += 1;
+                continue;
+            }
+            '\u{4e00}'..='\u{9fff}' | '\u{3400}'..='\u{4dbf}' => 0,
+            '\u{ac00}'..='\u{d7af}' | '\u{1100}'..='\u{11ff}' | '\u{3130}'..='\u{318f}' => 1,
+            '\u{0400}'..='\u{04ff}' => 2,
+            '\u{0600}'..='\u{06ff}' => 3,
+            '\u{0e00}'..='\u{0e7f}' => 4,
+            '\u{0590}'..='\u{05ff}' => 5,
+            '\u{0370}'..='\u{03ff}' => 6,
+            '\u{0900}'..='\u{097f}' => 7,
+            c if c.is_alphabetic() && c <= '\u{024f}' => 8,
+            _ => continue,
+        };
+        scripts[i].1 += 1;
+    }
+
+    // kana is decisive since Japanese texts are often mostly kanji
+    if kana > 0 {
+        return Some("ja");
+    }
+    let (script, count) = scripts.iter().max_by_key(|(_, n)| *n)?;
+    match (*script, count) {
+        (_, 0) => None,
+        ("latin", _) => detect_latin(&text),
+        (s, _) => Some(s),
+    }
+}
+
+fn detect_latin(text: &str) -> Option<&'static str> {
+    let words = text
+        .split(|c: char| !c.is_alphabetic() && c != '\'')
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>();
+    LATIN
+        .iter()
+        .map(|(lang, common, ngrams)| {
+            let score = words.iter().filter(|w| common.contains(w)).count() * 2
+                + ngrams
+                    .iter()
+                    .map(|n| text.matches(n).count())
+                    .sum::<usize>();
+            (*lang, score)
+        })
+        .filter(|(_, score)| *score > 0)
+        .max_by_key(|(_, score)| *score)
+        .map(|(lang, _)| lang)
+}
+
+#[test]
+fn test_detect() {
+    assert_eq!(Some("ja"), detect("今日の作画最高 #anime"));
+    assert_eq!(Some("zh"), detect("今天的作画太好了"));
+    assert_eq!(Some("ko"), detect("작화 최고"));
+    assert_eq!(
+        Some("en"),
+        detect("The animation of this episode is amazing")
+    );
+    assert_eq!(
+        Some("es"),
+        detect("¡Qué episodio! La animación es muy buena")
+    );
+    assert_eq!(None, detect("#anime https://t.co/a 🎉"));
+
+    let tweet = |lang: &str, text: &str| Tweet {
+        lang: Some(lang.to_string()),
+        full_text: text.to_string(),
+        ..Tweet::default()
+    };
+    let filter = LangFilter {
+        langs: vec!["ja".to_string(), "en".to_string()],
+        mail: None,
+    };
+    assert!(filter.accepts(&tweet("ja", "")));
+    assert!(!filter.accepts(&tweet("es", "")));
+    assert!(filter.accepts(&tweet("und", "The best")));
+    assert!(!filter.accepts(&tweet("qme", "Qué bueno, muy bueno")));
+    assert!(filter.accepts(&tweet("qht", "#anime")));
+
+    let filter = LangFilter {
+        langs: vec!["in".to_string(), "he".to_string()],
+        mail: None,
+    };
+    assert!(filter.accepts(&tweet("und", "Aku suka banget sama anime ini")));
+    assert!(filter.accepts(&tweet("iw", "")));
+    assert!(filter.accepts(&tweet("in-ID", "")));
+    assert!(!filter.accepts(&tweet("ja", "")));
+}
@@ -1,3 +1,6 @@
+pub mod anonymize;
+pub mod filter;
 pub mod iter;
+pub mod lang;
 pub mod nicodo;
 pub mod twitter;
@@ -0,0 +1,216 @@
+use super::{Chat, ThreadInfo};
+use chrono::{FixedOffset, TimeZone};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::Write;
+use tracing::debug;
+
+#[derive(Serialize)]
+struct LegacyThread<'a> {
+    resultcode: u32,
+    thread: &'a str,
+    server_time: u64,
+    last_res: usize,
+    ticket: &'a str,
+    revision: u32,
+}
+
+#[derive(Serialize)]
+struct LegacyChat<'a> {
+    thread: &'a str,
+    no: usize,
+    vpos: u64,
+    date: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_usec: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fork: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    premium: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anonymity: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mail: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<&'a str>,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Comment<'a> {
+    id: String,
+    no: usize,
+    vpos_ms: u64,
+    body: &'a str,
+    commands: Vec<&'a str>,
+    user_id: &'a str,
+    is_premium: bool,
+    score: i32,
+    posted_at: String,
+    nicoru_count: u32,
+    nicoru_id: Option<String>,
+    source: &'a str,
+    is_my_post: bool,
+}
+
+fn written<'a, C: IntoIterator<Item = &'a Chat>>(chats: C) -> Vec<&'a Chat> {
+    chats
+        .into_iter()
+        .filter(|c| !c.content.is_empty())
+        .collect()
+}
+
+/// Writes chats in the legacy JSON format, which is an array of `thread` and `chat` objects.
+/// Chats are numbered as `write_xml` does.
+pub fn write_json<'a, W: Write, C: IntoIterator<Item = &'a Chat>>(
+    writer: W,
+    chats: C,
+    info: &ThreadInfo,
+) -> serde_json::Result<()> {
+    let chats = written(chats);
+    debug!(chats = chats.len(), "writing legacy JSON");
+
+    let thread = LegacyThread {
+        resultcode: 0,
+        thread: &info.thread,
+        server_time: info.server_time(&chats),
+        last_res: chats.len(),
+        ticket: &info.ticket,
+        revision: info.revision,
+    };
+    let mut items = vec![
+        json!({ "thread": thread }),
+        json!({ "global_num_res": { "thread": info.thread, "num_res": chats.len() } }),
+    ];
+    items.extend(chats.iter().enumerate().map(|(no, c)| {
+        json!({
+            "chat": LegacyChat {
+                thread: c.thread.as_ref().unwrap_or(&info.thread),
+                no: no + 1,
+                vpos: c.vpos,
+                date: c.date,
+                date_usec: c.date_usec,
+                fork: c.fork,
+                premium: c.premium,
+                anonymity: c.anonymity,
+                score: c.score,
+                deleted: c.deleted,
+                mail: c.mail.as_deref(),
+                user_id: c.user_id.as_deref(),
+                content: &c.content,
+            }
+        })
+    }));
+
+    serde_json::to_writer(writer, &items)
+}
+
+/// Writes chats in the nvcomment format with `threads[].comments[]`.
+/// Owner comments are written to the `owner` fork and the others to `main`.
+pub fn write_nvcomment<'a, W: Write, C: IntoIterator<Item = &'a Chat>>(
+    writer: W,
+    chats: C,
+    info: &ThreadInfo,
+) -> serde_json::Result<()> {
+    let chats = written(chats);
+    debug!(chats = chats.len(), "writing nvcomment JSON");
+
+    // niconico shows times in JST
+    let jst = FixedOffset::east(9 * 3600);
+    let threads = [("owner", true), ("main", false)]
+        .iter()
+        .map(|&(fork, owner)| {
+            let comments = chats
+                .iter()
+                .filter(|c| (c.fork == Some(1)) == owner)
+                .enumerate()
+                .map(|(no, c)| Comment {
+                    id: c.id.clone().unwrap_or_else(|| (no + 1).to_string()),
+                    no: no + 1,
+                    vpos_ms: c.vpos * 10,
+                    body: &c.content,
+                    commands: c
+                        .mail
+                        .as_deref()
+                        .map(|m| m.split_whitespace().collect())
+                        .unwrap_or_default(),
+                    user_id: c.user_id.as_deref().unwrap_or(""),
+                    is_premium: c.premium == Some(1),
+                    score: c.score.unwrap_or(0),
+                    posted_at: jst
+                        .timestamp(c.date as i64, c.date_usec.unwrap_or(0) * 1000)
+                        .to_rfc3339(),
+                    nicoru_count: 0,
+                    nicoru_id: None,
+                    source: if owner { "owner" } else { "trunk" },
+                    is_my_post: false,
+                })
+                .collect::<Vec<_>>();
+            json!({
+                "id": info.thread,
+                "fork": fork,
+                "commentCount": comments.len(),
+                "comments": comments,
+            })
+        })
+        .collect::<Vec<Value>>();
+
+    serde_json::to_writer(
+        writer,
+        &json!({
+            "meta": { "status": 200 },
+            "data": {
+                "globalComments": [{ "count": chats.len(), "fork": "main" }],
+                "threads": threads,
+            },
+        }),
+    )
+}
+
+#[test]
+fn test_write_json() {
+    let chats = [
+        Chat {
+            date: 1596294000,
+            vpos: 150,
+            user_id: Some("a".to_string()),
+            mail: Some("184 red".to_string()),
+            content: "a".to_string(),
+            ..Chat::default()
+        },
+        Chat {
+            date: 1596294001,
+            vpos: 250,
+            fork: Some(1),
+            content: "b".to_string(),
+            ..Chat::default()
+        },
+    ];
+    let info = ThreadInfo::default();
+
+    let mut json = Vec::new();
+    write_json(&mut json, chats.iter(), &info).unwrap();
+    let json: Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(2, json[0]["thread"]["last_res"]);
+    assert_eq!(150, json[2]["chat"]["vpos"]);
+    assert_eq!("184 red", json[2]["chat"]["mail"]);
+    assert_eq!(1, json[3]["chat"]["fork"]);
+
+    let mut json = Vec::new();
+    write_nvcomment(&mut json, chats.iter(), &info).unwrap();
+    let json: Value = serde_json::from_slice(&json).unwrap();
+    let threads = &json["data"]["threads"];
+    assert_eq!("owner", threads[0]["fork"]);
+    assert_eq!("b", threads[0]["comments"][0]["body"]);
+    assert_eq!(1500, threads[1]["comments"][0]["vposMs"]);
+    assert_eq!(json!(["184", "red"]), threads[1]["comments"][0]["commands"]);
+    assert_eq!(
+        "2020-08-02T00:00:00+09:00",
+        threads[1]["comments"][0]["postedAt"]
+    );
+}
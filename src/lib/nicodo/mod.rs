@@ -1,15 +1,30 @@
 use serde::Deserialize;
 
+mod json;
 mod xml;
 
+pub use json::*;
 pub use xml::*;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct Chat {
     pub date: u64,
+    /// Microseconds added to `date`
+    pub date_usec: Option<u32>,
     pub vpos: u64,
     pub user_id: Option<String>,
     pub id: Option<String>,
     pub mail: Option<String>,
     pub content: String,
+    /// Thread ID. The one of `ThreadInfo` is used when not specified.
+    pub thread: Option<String>,
+    /// `1` for owner comments
+    pub fork: Option<u32>,
+    pub premium: Option<u32>,
+    /// `1` for anonymous comments
+    pub anonymity: Option<u32>,
+    /// NG score, which is zero or negative
+    pub score: Option<i32>,
+    /// Non-zero for deleted comments
+    pub deleted: Option<u32>,
 }
@@ -1,59 +1,122 @@
 use super::Chat;
 use quick_xml::{
     events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event},
-    Writer,
+    Reader, Writer,
 };
-use std::io::Write;
+use std::{
+    io::{BufRead, Write},
+    str::FromStr,
+};
+use tracing::debug;
 
 pub use quick_xml::{Error as XMLError, Result as XMLResult};
 
-pub fn write_xml<'a, W: Write, C: ExactSizeIterator<Item = &'a Chat>>(
+/// Attributes of `thread` and `view_counter` in XML files.
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    /// Thread ID written to `thread` and each `chat`
+    pub thread: String,
+    pub ticket: String,
+    pub revision: u32,
+    /// UNIX time of the server. The date of the last chat is used when not specified.
+    pub server_time: Option<u64>,
+    /// Video ID like `sm9`
+    pub video_id: Option<String>,
+    pub view_counter: u64,
+    pub mylist_counter: u64,
+}
+
+impl Default for ThreadInfo {
+    fn default() -> Self {
+        Self {
+            thread: "1".to_string(),
+            ticket: "0x0".to_string(),
+            revision: 1,
+            server_time: None,
+            video_id: None,
+            view_counter: 0,
+            mylist_counter: 0,
+        }
+    }
+}
+
+impl ThreadInfo {
+    pub(super) fn server_time(&self, chats: &[&Chat]) -> u64 {
+        self.server_time
+            .or_else(|| chats.iter().map(|c| c.date).max())
+            .unwrap_or(0)
+    }
+}
+
+/// Writes chats with `no` from 1. Chats with empty contents are skipped and not numbered.
+pub fn write_xml<'a, W: Write, C: IntoIterator<Item = &'a Chat>>(
     writer: W,
     chats: C,
+    info: &ThreadInfo,
 ) -> XMLResult<()> {
+    let chats = chats
+        .into_iter()
+        .filter(|c| !c.content.is_empty())
+        .collect::<Vec<_>>();
     let len = chats.len();
-    if len == 0 {
-        return Ok(());
-    }
+    debug!(chats = len, thread = %info.thread, "writing XML");
 
-    let mut w = Writer::new_with_indent(writer, ' ' as u8, 0);
+    let server_time = info.server_time(&chats);
+    let mut w = Writer::new_with_indent(writer, b' ', 0);
 
     for e in &[
         Event::Decl(BytesDecl::new(b"1.0", Some(b"utf-8"), None)),
         Event::Start(BytesStart::owned(b"packet".to_vec(), "packet".len())),
         {
             let mut e = BytesStart::owned(b"thread".to_vec(), "thread".len());
-            e.push_attribute(("last_res", &(len - 1).to_string() as &str));
-            e.push_attribute(("ticket", ""));
+            e.push_attribute(("resultcode", "0"));
+            e.push_attribute(("thread", &info.thread as &str));
+            e.push_attribute(("last_res", &len.to_string() as &str));
+            e.push_attribute(("ticket", &info.ticket as &str));
+            e.push_attribute(("revision", &info.revision.to_string() as &str));
+            e.push_attribute(("server_time", &server_time.to_string() as &str));
             Event::Empty(e)
         },
         {
             let mut e = BytesStart::owned(b"view_counter".to_vec(), "view_counter".len());
-            e.push_attribute(("video", "0"));
+            e.push_attribute(("video", &info.view_counter.to_string() as &str));
+            if let Some(id) = info.video_id.as_ref() {
+                e.push_attribute(("id", id as &str));
+            }
+            e.push_attribute(("mylist", &info.mylist_counter.to_string() as &str));
+            Event::Empty(e)
+        },
+        {
+            let mut e = BytesStart::owned(b"global_num_res".to_vec(), "global_num_res".len());
+            e.push_attribute(("thread", &info.thread as &str));
+            e.push_attribute(("num_res", &len.to_string() as &str));
             Event::Empty(e)
         },
     ] {
         w.write_event(e)?;
     }
 
-    for (no, c) in chats.enumerate() {
-        if c.content.is_empty() {
-            continue;
-        }
-
+    for (no, c) in chats.into_iter().enumerate() {
         w.write_event({
             let mut e = BytesStart::owned(b"chat".to_vec(), "chat".len());
-            e.push_attribute(("date", &c.date.to_string() as &str));
-            e.push_attribute(("vpos", &c.vpos.to_string() as &str));
+            e.push_attribute(("thread", c.thread.as_ref().unwrap_or(&info.thread) as &str));
             e.push_attribute(("no", &(no + 1).to_string() as &str));
-            if let Some(user_id) = c.user_id.as_ref() {
-                e.push_attribute(("user_id", &user_id.to_string() as &str));
-            }
-            if let Some(mail) = c.mail.as_ref() {
-                e.push_attribute(("mail", &mail.to_string() as &str));
-            }
-            if let Some(id) = c.id.as_ref() {
-                e.push_attribute(("id", &id.to_string() as &str));
+            e.push_attribute(("vpos", &c.vpos.to_string() as &str));
+            e.push_attribute(("date", &c.date.to_string() as &str));
+            for (key, value) in &[
+                ("date_usec", c.date_usec.map(|v| v.to_string())),
+                ("fork", c.fork.map(|v| v.to_string())),
+                ("premium", c.premium.map(|v| v.to_string())),
+                ("anonymity", c.anonymity.map(|v| v.to_string())),
+                ("score", c.score.map(|v| v.to_string())),
+                ("deleted", c.deleted.map(|v| v.to_string())),
+                ("mail", c.mail.clone()),
+                ("user_id", c.user_id.clone()),
+                ("id", c.id.clone()),
+            ] {
+                if let Some(value) = value.as_ref() {
+                    e.push_attribute((*key, value as &str));
+                }
             }
             Event::Start(e)
         })
@@ -66,6 +129,184 @@ pub fn write_xml<'a, W: Write, C: ExactSizeIterator<Item = &'a Chat>>(
     }
 
     w.write_event(Event::End(BytesEnd::borrowed(b"packet")))?;
+    debug!("finished writing XML");
 
     Ok(())
 }
+
+/// Reads chats from a niconico XML file, with attributes of the first `thread` and `view_counter`
+/// when the file has a `thread`. Other elements are ignored.
+pub fn read_xml<R: BufRead>(reader: R) -> XMLResult<(Vec<Chat>, Option<ThreadInfo>)> {
+    let mut r = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    let mut chats = Vec::new();
+    let mut current: Option<Chat> = None;
+    let mut info: Option<ThreadInfo> = None;
+    let mut counter = None;
+
+    loop {
+        match r.read_event(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e)
+                if e.name() == b"thread" && info.is_none() =>
+            {
+                info = Some(read_thread(e, &r)?)
+            }
+            Event::Start(ref e) | Event::Empty(ref e)
+                if e.name() == b"view_counter" && counter.is_none() =>
+            {
+                counter = Some(read_thread(e, &r)?)
+            }
+            Event::Start(ref e) if e.name() == b"chat" => current = Some(read_chat(e, &r)?),
+            Event::Empty(ref e) if e.name() == b"chat" => chats.push(read_chat(e, &r)?),
+            Event::Text(ref e) => {
+                if let Some(c) = current.as_mut() {
+                    c.content.push_str(&e.unescape_and_decode(&r)?);
+                }
+            }
+            Event::End(ref e) if e.name() == b"chat" => chats.extend(current.take()),
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let info = info.map(|i| match counter {
+        Some(c) => ThreadInfo {
+            video_id: c.video_id,
+            view_counter: c.view_counter,
+            mylist_counter: c.mylist_counter,
+            ..i
+        },
+        None => i,
+    });
+    Ok((chats, info))
+}
+
+/// Reads attributes of `thread` or `view_counter` into `ThreadInfo`.
+fn read_thread<R: BufRead>(e: &BytesStart, r: &Reader<R>) -> XMLResult<ThreadInfo> {
+    let mut info = ThreadInfo::default();
+    for a in e.attributes() {
+        let a = a?;
+        let value = a.unescape_and_decode_value(r)?;
+        match (e.name(), a.key) {
+            (b"thread", b"thread") => info.thread = value,
+            (b"thread", b"ticket") => info.ticket = value,
+            (b"thread", b"revision") => info.revision = number(a.key, &value)?,
+            (b"thread", b"server_time") => info.server_time = Some(number(a.key, &value)?),
+            (b"view_counter", b"video") => info.view_counter = number(a.key, &value)?,
+            (b"view_counter", b"id") => info.video_id = Some(value),
+            (b"view_counter", b"mylist") => info.mylist_counter = number(a.key, &value)?,
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+fn read_chat<R: BufRead>(e: &BytesStart, r: &Reader<R>) -> XMLResult<Chat> {
+    let mut c = Chat::default();
+    for a in e.attributes() {
+        let a = a?;
+        let value = a.unescape_and_decode_value(r)?;
+        match a.key {
+            b"date" => c.date = number(a.key, &value)?,
+            b"date_usec" => c.date_usec = Some(number(a.key, &value)?),
+            b"vpos" => c.vpos = number(a.key, &value)?,
+            b"user_id" => c.user_id = Some(value),
+            b"id" => c.id = Some(value),
+            b"mail" => c.mail = Some(value),
+            b"thread" => c.thread = Some(value),
+            b"fork" => c.fork = Some(number(a.key, &value)?),
+            b"premium" => c.premium = Some(number(a.key, &value)?),
+            b"anonymity" => c.anonymity = Some(number(a.key, &value)?),
+            b"score" => c.score = Some(number(a.key, &value)?),
+            b"deleted" => c.deleted = Some(number(a.key, &value)?),
+            _ => {}
+        }
+    }
+    Ok(c)
+}
+
+fn number<T: FromStr>(key: &[u8], value: &str) -> XMLResult<T> {
+    value.parse().map_err(|_| {
+        XMLError::UnexpectedToken(format!("{}=\"{}\"", String::from_utf8_lossy(key), value))
+    })
+}
+
+#[test]
+fn test_read_xml() {
+    let chats = [Chat {
+        date: 1596294000,
+        vpos: 150,
+        user_id: Some("user".to_string()),
+        id: Some("1".to_string()),
+        mail: None,
+        content: "a < b & c".to_string(),
+        date_usec: Some(123456),
+        fork: Some(1),
+        premium: Some(1),
+        anonymity: Some(1),
+        score: Some(-1000),
+        deleted: Some(0),
+        thread: Some("2".to_string()),
+    }];
+    let mut xml = Vec::new();
+    let info = ThreadInfo {
+        thread: "2".to_string(),
+        server_time: Some(1596294100),
+        video_id: Some("sm9".to_string()),
+        view_counter: 10,
+        ..ThreadInfo::default()
+    };
+    write_xml(&mut xml, chats.iter(), &info).unwrap();
+
+    let (read, read_info) = read_xml(&xml[..]).unwrap();
+    let read_info = read_info.unwrap();
+    assert_eq!("2", read_info.thread);
+    assert_eq!(Some(1596294100), read_info.server_time);
+    assert_eq!(Some("sm9"), read_info.video_id.as_deref());
+    assert_eq!(10, read_info.view_counter);
+    assert_eq!(1, read.len());
+    assert_eq!(150, read[0].vpos);
+    assert_eq!(Some("user"), read[0].user_id.as_deref());
+    assert_eq!("a < b & c", read[0].content);
+    assert_eq!(Some(123456), read[0].date_usec);
+    assert_eq!(Some(1), read[0].fork);
+    assert_eq!(Some(-1000), read[0].score);
+    assert_eq!(Some("2"), read[0].thread.as_deref());
+}
+
+#[test]
+fn test_write_xml() {
+    let chat = |vpos: u64, content: &str| Chat {
+        date: 1596294000 + vpos / 100,
+        vpos,
+        user_id: Some("a".to_string()),
+        id: None,
+        mail: Some("184".to_string()),
+        content: content.to_string(),
+        ..Chat::default()
+    };
+    let chats = [chat(0, "a"), chat(100, ""), chat(200, "b")];
+    let mut xml = Vec::new();
+    write_xml(
+        &mut xml,
+        chats.iter(),
+        &ThreadInfo {
+            thread: "1596294000".to_string(),
+            video_id: Some("sm9".to_string()),
+            ..ThreadInfo::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<packet>
+<thread resultcode="0" thread="1596294000" last_res="2" ticket="0x0" revision="1" server_time="1596294002"/>
+<view_counter video="0" id="sm9" mylist="0"/>
+<global_num_res thread="1596294000" num_res="2"/>
+<chat thread="1596294000" no="1" vpos="0" date="1596294000" mail="184" user_id="a">a</chat>
+<chat thread="1596294000" no="2" vpos="200" date="1596294002" mail="184" user_id="a">b</chat>
+</packet>"#,
+        String::from_utf8(xml).unwrap()
+    );
+}
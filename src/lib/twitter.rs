@@ -1,16 +1,31 @@
 use chrono::{DateTime, TimeZone, Utc};
 use derive_more::From;
-use futures::stream::{try_unfold, TryStream};
+use futures::{
+    pin_mut,
+    stream::{try_unfold, TryStream, TryStreamExt},
+};
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use reqwest::{
     header::{HeaderMap, HeaderValue, InvalidHeaderValue},
-    Url,
+    StatusCode, Url,
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
-use std::{borrow::Cow, collections::HashMap, fmt, num::ParseIntError, str::FromStr};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    iter::once,
+    num::ParseIntError,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 use thiserror::Error;
+use tracing::{debug, info, warn};
 
 const DEFAULT_PARAMS: &[(&str, &str)] = &[
     ("include_profile_interstitial_type", "1"),
@@ -57,6 +72,8 @@ pub enum CookieError {
     TwitterSessMissing,
     #[error("cookie ct0 missing")]
     Ct0Missing,
+    #[error("invalid HAR file: {0}")]
+    Har(serde_json::Error),
 }
 
 fn cookie_map<'a>(s: &'a str) -> HashMap<&'a str, &'a str> {
@@ -66,12 +83,88 @@ fn cookie_map<'a>(s: &'a str) -> HashMap<&'a str, &'a str> {
         .collect()
 }
 
-impl FromStr for Cookie {
-    type Err = CookieError;
+fn is_twitter_domain(domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.');
+    domain == "twitter.com" || domain.ends_with(".twitter.com")
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let map = cookie_map(s);
+fn netscape_cookie_map(s: &str) -> HashMap<&str, &str> {
+    s.lines()
+        .map(|l| l.trim_start_matches("#HttpOnly_"))
+        .filter(|l| !l.starts_with('#'))
+        .map(|l| l.trim_end_matches('\r').split('\t').collect::<Vec<_>>())
+        .filter(|f| f.len() >= 7 && is_twitter_domain(f[0]))
+        .map(|f| (f[5], f[6]))
+        .collect()
+}
 
+#[derive(Debug, Deserialize)]
+struct Har {
+    log: HarLog,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarLog {
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarEntry {
+    request: HarMessage,
+    response: HarMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarMessage {
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    cookies: Vec<HarCookie>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarCookie {
+    name: String,
+    value: String,
+}
+
+impl Cookie {
+    /// Reads cookies from a Netscape cookies.txt export, a HAR file or a raw cookie header.
+    pub fn import(s: &str) -> Result<Self, CookieError> {
+        let t = s.trim_start();
+        if t.starts_with('{') {
+            Self::from_har(t)
+        } else if t.contains('\t') {
+            Self::from_netscape(t)
+        } else {
+            t.trim().parse()
+        }
+    }
+
+    pub fn from_netscape(s: &str) -> Result<Self, CookieError> {
+        Self::from_map(&netscape_cookie_map(s))
+    }
+
+    pub fn from_har(s: &str) -> Result<Self, CookieError> {
+        let har = serde_json::from_str::<Har>(s)?;
+        let cookies = har
+            .log
+            .entries
+            .iter()
+            .filter(|e| {
+                Url::parse(&e.request.url)
+                    .ok()
+                    .and_then(|u| u.host_str().map(is_twitter_domain))
+                    .unwrap_or(false)
+            })
+            .flat_map(|e| e.request.cookies.iter().chain(e.response.cookies.iter()))
+            .filter(|c| !c.value.is_empty())
+            .map(|c| (&c.name as &str, &c.value as &str))
+            .collect::<HashMap<_, _>>();
+        Self::from_map(&cookies)
+    }
+
+    fn from_map(map: &HashMap<&str, &str>) -> Result<Self, CookieError> {
         let auth_token = map.get("auth_token").ok_or(CookieError::AuthTokenMissing)?;
         let twitter_sess = map
             .get("_twitter_sess")
@@ -86,6 +179,14 @@ impl FromStr for Cookie {
     }
 }
 
+impl FromStr for Cookie {
+    type Err = CookieError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_map(&cookie_map(s))
+    }
+}
+
 impl fmt::Display for Cookie {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -122,23 +223,67 @@ pub enum Error {
     Request(reqwest::Error),
     #[error("{0}")]
     URL(url::ParseError),
+    #[error("rate limited")]
+    RateLimited,
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    /// Index of the auth which has the invalid value
+    #[error("{1}")]
+    Header(usize, InvalidHeaderValue),
+    #[error("no auth information")]
+    Empty,
 }
 
 #[derive(Debug)]
 pub struct TwitterClient {
-    client: reqwest::Client,
+    clients: Vec<reqwest::Client>,
+    current: Arc<AtomicUsize>,
 }
 
 impl TwitterClient {
-    pub fn new<A: Into<Auth>>(auth: A) -> Result<Self, ()> {
+    pub fn new<A: Into<Auth>>(auth: A) -> Result<Self, AuthError> {
+        Self::with_auths(once(auth))
+    }
+
+    /// Creates a client which switches to the next auth when the current one is rate limited.
+    pub fn with_auths<A: Into<Auth>, I: IntoIterator<Item = A>>(
+        auths: I,
+    ) -> Result<Self, AuthError> {
+        let clients = auths
+            .into_iter()
+            .enumerate()
+            .map(|(i, a)| {
+                Ok(reqwest::Client::builder()
+                    .default_headers(a.into().headers().map_err(|e| AuthError::Header(i, e))?)
+                    .build()
+                    .unwrap())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if clients.is_empty() {
+            return Err(AuthError::Empty);
+        }
         Ok(Self {
-            client: reqwest::Client::builder()
-                .default_headers(auth.into().headers().map_err(|_| ())?)
-                .build()
-                .unwrap(),
+            clients,
+            current: Arc::new(AtomicUsize::new(0)),
         })
     }
 
+    /// Sends a single search request to check that the auth information is accepted.
+    pub async fn verify(&self) -> Result<(), Error> {
+        let s = self
+            .search_tweets(Query {
+                text: "twitter".to_string(),
+                since: None,
+                until: None,
+            })
+            .into_stream();
+        pin_mut!(s);
+        s.try_next().await?;
+        Ok(())
+    }
+
     pub fn search_tweets(&self, query: Query) -> impl TryStream<Ok = Response, Error = Error> {
         lazy_static! {
             static ref URL: Url = Url::parse_with_params(
@@ -149,15 +294,19 @@ impl TwitterClient {
         }
 
         struct Context {
-            client: reqwest::Client,
+            clients: Vec<reqwest::Client>,
+            current: Arc<AtomicUsize>,
             query: String,
             cursor: Option<String>,
             finished: bool,
         }
 
+        let query = query.to_string();
+        info!(query = %query, "searching tweets");
         let ctx = Context {
-            client: self.client.clone(),
-            query: query.to_string(),
+            clients: self.clients.clone(),
+            current: self.current.clone(),
+            query,
             cursor: None,
             finished: false,
         };
@@ -167,19 +316,42 @@ impl TwitterClient {
                 return Ok(None);
             }
 
-            let mut req = ctx.client.get(URL.to_owned()).query(&[("q", &ctx.query)]);
-            if let Some(cursor) = ctx.cursor.as_ref() {
-                req = req.query(&[("cursor", cursor)]);
-            }
-
-            let res = req
-                .send()
-                .await?
-                .error_for_status()?
-                .json::<RawResponse>()
-                .await?;
+            let res = loop {
+                let current = ctx.current.load(Ordering::SeqCst);
+                let mut req = ctx.clients[current]
+                    .get(URL.to_owned())
+                    .query(&[("q", &ctx.query)]);
+                if let Some(cursor) = ctx.cursor.as_ref() {
+                    req = req.query(&[("cursor", cursor)]);
+                }
+
+                // auth information is in headers, so the URL is safe to log
+                let req = req.build()?;
+                debug!(url = %req.url(), client = current, "sending a search request");
+                let res = ctx.clients[current].execute(req).await?;
+                debug!(status = %res.status(), "received a search response");
+                if res.status() != StatusCode::TOO_MANY_REQUESTS {
+                    break res;
+                }
+                if current + 1 >= ctx.clients.len() {
+                    warn!("rate limited with all profiles");
+                    return Err(Error::RateLimited);
+                }
+                warn!(
+                    client = current + 1,
+                    "rate limited, switching to the next profile"
+                );
+                ctx.current.store(current + 1, Ordering::SeqCst);
+            };
+
+            let res = res.error_for_status()?.json::<RawResponse>().await?;
 
             let cursor = res.next_cursor().map(|s| s.to_string());
+            debug!(
+                tweets = res.global_objects.tweets.len(),
+                cursor = ?cursor,
+                "fetched a page"
+            );
             if res.global_objects.tweets.is_empty() || cursor.is_none() {
                 Ok(Some((
                     res.into(),
@@ -196,7 +368,10 @@ impl TwitterClient {
     }
 }
 
-#[derive(Debug)]
+/// Maximum length of search queries accepted by Twitter.
+pub const MAX_QUERY_LENGTH: usize = 500;
+
+#[derive(Debug, Clone)]
 pub struct Query {
     pub since: Option<String>,
     pub until: Option<String>,
@@ -216,19 +391,113 @@ impl ToString for Query {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl Query {
+    /// Splits a query with a long OR-list at the top level into queries within `max` characters
+    /// including `since:` and `until:`. Queries which cannot be split are kept as they are.
+    pub fn split(&self, max: usize) -> Vec<Query> {
+        if self.to_string().chars().count() <= max {
+            return vec![self.clone()];
+        }
+
+        let reserved = Query {
+            text: String::new(),
+            ..self.clone()
+        }
+        .to_string()
+        .chars()
+        .count();
+        let mut queries = Vec::new();
+        let mut terms: Vec<&str> = Vec::new();
+        let mut len = reserved;
+        for term in or_terms(&self.text) {
+            let n = term.chars().count();
+            if !terms.is_empty() && len + " OR ".len() + n > max {
+                queries.push(terms.join(" OR "));
+                terms.clear();
+                len = reserved;
+            }
+            len += if terms.is_empty() {
+                n
+            } else {
+                " OR ".len() + n
+            };
+            terms.push(term);
+        }
+        queries.push(terms.join(" OR "));
+
+        queries
+            .into_iter()
+            .map(|text| Query {
+                text,
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+/// Splits `a OR (b OR c) OR "d OR e"` into `a`, `(b OR c)` and `"d OR e"`.
+fn or_terms(s: &str) -> Vec<&str> {
+    let mut terms = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted && depth > 0 => depth -= 1,
+            ' ' if !quoted && depth == 0 && s[i..].starts_with(" OR ") => {
+                terms.push(s[start..i].trim());
+                start = i + 4;
+            }
+            _ => {}
+        }
+    }
+    terms.push(s[start..].trim());
+    terms.retain(|t| !t.is_empty());
+    terms
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Tweet {
     pub id: String,
     pub created_at: Option<DateTime<Utc>>,
     pub full_text: String,
     pub user_id: String,
+    /// Fields which are not typed
     pub extra: HashMap<String, Value>,
     pub user: Option<User>,
+    /// Search queries which found the tweet
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub queries: Vec<String>,
+    pub favorite_count: Option<u64>,
+    pub retweet_count: Option<u64>,
+    pub reply_count: Option<u64>,
+    pub quote_count: Option<u64>,
+    /// Language detected by Twitter like `ja`, or `und` when unknown
+    pub lang: Option<String>,
+    /// Media are taken from `extended_entities` to have all of them
+    #[serde(default)]
+    pub entities: Entities,
+    pub in_reply_to_status_id: Option<String>,
+    pub in_reply_to_user_id: Option<String>,
+    pub in_reply_to_screen_name: Option<String>,
+    pub retweeted_status_id: Option<String>,
+    pub quoted_status_id: Option<String>,
+    /// Quoted tweet when it is in the same response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quoted_status: Option<Box<Tweet>>,
+    /// HTML link of the client which posted the tweet
+    pub source: Option<String>,
 }
 
 impl From<RawTweet> for Tweet {
     fn from(tweet: RawTweet) -> Self {
         let created_at = tweet.id_str.datetime().ok();
+        let mut entities = tweet.entities;
+        if let Some(e) = tweet.extended_entities {
+            entities.media = e.media;
+        }
         Self {
             id: tweet.id_str.0,
             created_at,
@@ -236,36 +505,138 @@ impl From<RawTweet> for Tweet {
             user_id: tweet.user_id_str,
             extra: tweet.extra,
             user: None,
+            queries: Vec::new(),
+            favorite_count: tweet.favorite_count,
+            retweet_count: tweet.retweet_count,
+            reply_count: tweet.reply_count,
+            quote_count: tweet.quote_count,
+            lang: tweet.lang,
+            entities,
+            in_reply_to_status_id: tweet.in_reply_to_status_id_str,
+            in_reply_to_user_id: tweet.in_reply_to_user_id_str,
+            in_reply_to_screen_name: tweet.in_reply_to_screen_name,
+            retweeted_status_id: tweet.retweeted_status_id_str,
+            quoted_status_id: tweet.quoted_status_id_str,
+            quoted_status: None,
+            source: tweet.source,
         }
     }
 }
 
+impl Tweet {
+    /// Whether the tweet is posted at or after `since` and before `until`.
+    /// The time is decoded from the ID when `created_at` is missing, and unknown times are kept.
+    pub fn is_within(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> bool {
+        match self
+            .created_at
+            .or_else(|| TweetID(self.id.clone()).datetime().ok())
+        {
+            Some(d) => since <= d && d < until,
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Entities {
+    #[serde(default)]
+    pub hashtags: Vec<Hashtag>,
+    #[serde(default)]
+    pub urls: Vec<UrlEntity>,
+    #[serde(default)]
+    pub user_mentions: Vec<Mention>,
+    #[serde(default)]
+    pub media: Vec<Media>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ExtendedEntities {
+    #[serde(default)]
+    media: Vec<Media>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Hashtag {
+    /// Text without `#`
+    pub text: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UrlEntity {
+    /// Shortened `t.co` URL in the text
+    pub url: String,
+    pub expanded_url: Option<String>,
+    pub display_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Mention {
+    pub id_str: String,
+    pub screen_name: String,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Media {
+    /// `photo`, `video` or `animated_gif`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Shortened `t.co` URL in the text
+    pub url: Option<String>,
+    pub media_url_https: Option<String>,
+    pub ext_alt_text: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct User {
     pub id: u64,
     pub id_str: String,
     pub name: String,
     pub screen_name: String,
+    pub followers_count: Option<u64>,
+    pub verified: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// Fields which are not typed
     #[serde(flatten)]
     pub extra: HashMap<String, Value>,
 }
 
+/// Parses dates like `Wed Oct 10 20:19:24 +0000 2018` of the API, or RFC 3339 of archives.
+fn deserialize_date<'de, D: Deserializer<'de>>(d: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    let s = match Option::<String>::deserialize(d)? {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    DateTime::parse_from_rfc3339(&s)
+        .or_else(|_| DateTime::parse_from_str(&s, "%a %b %d %H:%M:%S %z %Y"))
+        .map(|d| Some(d.with_timezone(&Utc)))
+        .map_err(serde::de::Error::custom)
+}
+
 pub type Response = Vec<Tweet>;
 
 impl From<RawResponse> for Response {
     fn from(res: RawResponse) -> Self {
         let users = res.global_objects.users;
+        let tweets = res.global_objects.tweets;
+        let convert = |t: &RawTweet| Tweet {
+            user: users.get(&t.user_id_str).map(|u| u.clone()),
+            ..t.clone().into()
+        };
 
-        res.global_objects
-            .tweets
-            .into_iter()
-            .map(|(_, tweet)| {
-                let user = users.get(&tweet.user_id_str).map(|u| u.clone());
-                (tweet, user)
-            })
-            .map(|(t, u)| Tweet {
-                user: u,
-                ..t.into()
+        tweets
+            .values()
+            .map(|t| {
+                let quoted_status = t
+                    .quoted_status_id_str
+                    .as_ref()
+                    .and_then(|id| tweets.get(id))
+                    .map(|q| Box::new(convert(q)));
+                Tweet {
+                    quoted_status,
+                    ..convert(t)
+                }
             })
             .collect()
     }
@@ -309,6 +680,20 @@ struct RawTweet {
     pub full_text: String,
     pub user_id: u64,
     pub user_id_str: String,
+    pub favorite_count: Option<u64>,
+    pub retweet_count: Option<u64>,
+    pub reply_count: Option<u64>,
+    pub quote_count: Option<u64>,
+    pub lang: Option<String>,
+    #[serde(default)]
+    pub entities: Entities,
+    pub extended_entities: Option<ExtendedEntities>,
+    pub in_reply_to_status_id_str: Option<String>,
+    pub in_reply_to_user_id_str: Option<String>,
+    pub in_reply_to_screen_name: Option<String>,
+    pub retweeted_status_id_str: Option<String>,
+    pub quoted_status_id_str: Option<String>,
+    pub source: Option<String>,
     #[serde(flatten)]
     pub extra: HashMap<String, Value>,
 }
@@ -373,11 +758,31 @@ fn test_cookie_map() {
     assert_eq!(expected, cookie_map("foo=bar; hoge=bar"));
 }
 
+#[test]
+fn test_cookie_import() {
+    let txt = "# Netscape HTTP Cookie File\n\
+        .twitter.com\tTRUE\t/\tTRUE\t0\tct0\tc\n\
+        #HttpOnly_.twitter.com\tTRUE\t/\tTRUE\t0\tauth_token\ta\n\
+        #HttpOnly_.twitter.com\tTRUE\t/\tTRUE\t0\t_twitter_sess\ts\n\
+        .example.com\tTRUE\t/\tTRUE\t0\tct0\tx\n";
+    let cookie = Cookie::import(txt).unwrap();
+    assert_eq!("auth_token=a; _twitter_sess=s; ct0=c", cookie.to_string());
+
+    let har = r#"{"log":{"entries":[
+        {"request":{"url":"https://example.com/","cookies":[{"name":"ct0","value":"x"}]},"response":{}},
+        {"request":{"url":"https://api.twitter.com/2/search/adaptive.json","cookies":[
+            {"name":"auth_token","value":"a"},{"name":"_twitter_sess","value":"s"},{"name":"ct0","value":"b"}
+        ]},"response":{"cookies":[{"name":"ct0","value":"c"}]}}
+    ]}}"#;
+    let cookie = Cookie::import(har).unwrap();
+    assert_eq!("auth_token=a; _twitter_sess=s; ct0=c", cookie.to_string());
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TweetID(pub String);
 
 impl TweetID {
-    fn datetime(&self) -> Result<DateTime<Utc>, ParseIntError> {
+    pub fn datetime(&self) -> Result<DateTime<Utc>, ParseIntError> {
         Ok(Utc.timestamp_millis((self.0.parse::<i64>()? >> 22) + 1288834974657))
     }
 }
@@ -391,3 +796,67 @@ fn test_tweet_id() {
             .unwrap()
     );
 }
+
+#[test]
+fn test_tweet_is_within() {
+    let tweet = Tweet {
+        id: "1289960487912783872".to_string(),
+        ..Tweet::default()
+    };
+    let at = Utc.timestamp_millis(1596385521282);
+    assert!(tweet.is_within(at, at + chrono::Duration::seconds(1)));
+    assert!(!tweet.is_within(at - chrono::Duration::seconds(1), at));
+}
+
+#[test]
+fn test_query_split() {
+    let query = Query {
+        text: "#a OR (#b OR #c) OR \"d OR e\" OR #f".to_string(),
+        since: Some("2020-08-01".to_string()),
+        until: None,
+    };
+    assert_eq!(1, query.split(100).len());
+    assert_eq!(
+        vec![
+            "#a OR (#b OR #c) since:2020-08-01",
+            "\"d OR e\" OR #f since:2020-08-01"
+        ],
+        query
+            .split(34)
+            .iter()
+            .map(|q| q.to_string())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_tweet_from_raw() {
+    let raw: RawTweet = serde_json::from_str(
+        r#"{
+            "id_str": "1289960487912783872", "full_text": "a", "user_id": 1, "user_id_str": "1",
+            "lang": "ja", "favorite_count": 3, "in_reply_to_status_id_str": "2",
+            "entities": {"hashtags": [{"text": "anime", "indices": [0, 6]}],
+                "media": [{"type": "photo"}]},
+            "extended_entities": {"media": [{"type": "photo"}, {"type": "video"}]},
+            "card": {}
+        }"#,
+    )
+    .unwrap();
+    let tweet = Tweet::from(raw);
+    assert_eq!(Some("ja"), tweet.lang.as_deref());
+    assert_eq!(Some(3), tweet.favorite_count);
+    assert_eq!(Some("2"), tweet.in_reply_to_status_id.as_deref());
+    assert_eq!("anime", tweet.entities.hashtags[0].text);
+    assert_eq!(2, tweet.entities.media.len());
+    assert!(tweet.extra.contains_key("card"));
+    assert!(!tweet.extra.contains_key("lang"));
+
+    let user: User = serde_json::from_str(
+        r#"{"id": 1, "id_str": "1", "name": "A", "screen_name": "a",
+            "created_at": "Wed Oct 10 20:19:24 +0000 2018"}"#,
+    )
+    .unwrap();
+    assert_eq!(Some(Utc.timestamp(1539202764, 0)), user.created_at);
+    let user: User = serde_json::from_value(serde_json::to_value(&user).unwrap()).unwrap();
+    assert_eq!(Some(Utc.timestamp(1539202764, 0)), user.created_at);
+}
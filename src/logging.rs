@@ -0,0 +1,48 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+};
+use tracing::Level;
+
+/// Shared log file written by all threads.
+#[derive(Clone)]
+struct LogFile(Arc<File>);
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self.0).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self.0).flush()
+    }
+}
+
+/// Starts logging with the level by the number of `-v`. Only warnings are printed by default,
+/// while a log file gets debug logs unless `-vvv` is given.
+pub fn init(verbose: u64, file: Option<&Path>) -> io::Result<()> {
+    let level = match (verbose, file.is_some()) {
+        (0, false) => Level::WARN,
+        (1, false) => Level::INFO,
+        (0..=2, _) => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr);
+
+    if let Some(path) = file {
+        let f = LogFile(Arc::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ));
+        builder
+            .with_ansi(false)
+            .with_writer(move || f.clone())
+            .init();
+    } else {
+        builder.init();
+    }
+    Ok(())
+}
@@ -1,130 +1,906 @@
-use clap::Clap;
+use batch::Manifest;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use clap::{AppSettings, ArgSettings, Clap};
 use derive_more::From;
 use dialoguer::{theme::ColorfulTheme, Input};
-use futures::stream::TryStreamExt;
-use std::fs::File;
+use format::Format;
+use job::Job;
+use merge::{Align, Source};
+use preset::Preset;
+use progress::Mode;
+use secrets::Storage;
+use shift::{Retime, Segment};
+use stats::Stats;
+use std::{
+    ffi::OsStr,
+    fs::{create_dir_all, read_to_string, File},
+    io::{stdout, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::exit,
+};
 use thiserror::Error;
-use tokio::task::{spawn_blocking, JoinError};
+use tokio::task::JoinError;
+use tracing::info;
 use twinicodo::{
-    iter::SortedTweetToChat,
-    nicodo::{write_xml, Chat, XMLError},
-    twitter::{CookieError, Error as TwitterError, Query, TwitterClient},
+    anonymize::AnonymizeChats,
+    filter::FilterChats,
+    iter::{Cleanup, ConvertOptions, SortedTweetToChat},
+    nicodo::{read_xml, Chat, ThreadInfo, XMLError},
+    twitter::{AuthError, Cookie, CookieError, Error as TwitterError, TwitterClient},
 };
 
+mod batch;
 mod config;
+mod datetime;
+mod format;
+mod job;
+mod logging;
+mod merge;
+mod preset;
+mod progress;
+mod schedule;
+mod secrets;
+mod shift;
+mod stats;
+mod template;
 
-type MainResult<T> = Result<T, MainError>;
+pub(crate) type MainResult<T> = Result<T, MainError>;
 
 #[derive(Error, Debug, From)]
-enum MainError {
+pub(crate) enum MainError {
     #[error("{0}")]
     IO(std::io::Error),
     #[error("{0}")]
     XML(XMLError),
     #[error("{0}")]
-    Confy(confy::ConfyError),
+    Config(config::Error),
     #[error("{0}")]
     Twitter(TwitterError),
     #[error("{0}")]
     Join(JoinError),
     #[error("{0}")]
     Cookie(CookieError),
-    #[error("auth error")]
-    Auth,
+    #[error("{0}")]
+    Template(template::Error),
+    #[error("{0}")]
+    Json(serde_json::Error),
+    #[error("{0}")]
+    Toml(toml::de::Error),
+    #[error("{0}")]
+    DateTime(datetime::Error),
+    #[error("invalid auth information ({0}): {1}")]
+    #[from(ignore)]
+    Auth(String, AuthError),
+    #[error("failed to verify auth information: {0}")]
+    #[from(ignore)]
+    Verify(TwitterError),
+    #[error(
+        "no valid auth information for profile {0}: \
+        set TWINICODO_BEARER and TWINICODO_COOKIE or run without --no-interactive"
+    )]
+    #[from(ignore)]
+    NoAuth(String),
+    #[error("profile not found: {0}")]
+    #[from(ignore)]
+    ProfileNotFound(String),
+    #[error("{0} jobs failed")]
+    #[from(ignore)]
+    BatchFailed(usize),
 }
 
 #[derive(Debug, Clap)]
-#[clap(about = "A command line tool to search tweets and convert into niconico XML file")]
+#[clap(
+    about = "A command line tool to search tweets and convert into niconico XML file",
+    setting = AppSettings::SubcommandsNegateReqs
+)]
 struct Opts {
-    text: String,
-    #[clap(long, short)]
-    since: String,
-    #[clap(long, short)]
-    until: String,
-    #[clap(long, short)]
+    #[clap(
+        setting = ArgSettings::Required,
+        about = "Search queries whose results are combined. Long OR-lists are split automatically"
+    )]
+    queries: Vec<String>,
+    #[clap(long, short, setting = ArgSettings::Required)]
+    since: Option<String>,
+    #[clap(long, short, setting = ArgSettings::Required)]
+    until: Option<String>,
+    #[clap(
+        long,
+        short,
+        about = "Output filename template with {query}, {since}, {until}, {count}, {format}, {episode} and {date:%Y%m%d}"
+    )]
     output: Option<String>,
+    #[clap(long, about = "Saves searched tweets into the JSON file for stats")]
+    archive: Option<PathBuf>,
     #[clap(long)]
     reset: bool,
+    #[clap(long, about = "Imports auth cookies from a cookies.txt or HAR file")]
+    import_cookies: Option<String>,
+    #[clap(long, short, global = true, about = "Auth profile to use")]
+    profile: Option<String>,
+    #[clap(
+        long,
+        global = true,
+        about = "Switches to other profiles when rate limited"
+    )]
+    rotate: bool,
+    #[clap(
+        long,
+        global = true,
+        about = "Reads auth cookies from a cookies.txt or HAR file without storing them"
+    )]
+    cookie_file: Option<String>,
+    #[clap(
+        long,
+        global = true,
+        about = "Fails instead of prompting for auth information"
+    )]
+    no_interactive: bool,
+    #[clap(
+        short,
+        long,
+        global = true,
+        parse(from_occurrences),
+        about = "Prints logs (-v for info, -vv for debug, -vvv for trace)"
+    )]
+    verbose: u64,
+    #[clap(
+        long,
+        global = true,
+        about = "Appends logs to the file instead of stderr"
+    )]
+    log_file: Option<PathBuf>,
+    #[clap(long, short, global = true, about = "Prints only errors and results")]
+    quiet: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "quiet",
+        about = "Prints progress as JSON lines for scripts"
+    )]
+    json_log: bool,
+    #[clap(flatten)]
+    convert: ConvertOpts,
+    #[clap(subcommand)]
+    cmd: Option<Command>,
+}
+
+impl Opts {
+    fn log_mode(&self) -> Mode {
+        if self.quiet {
+            Mode::Quiet
+        } else if self.json_log {
+            Mode::Json
+        } else {
+            Mode::text()
+        }
+    }
+}
+
+#[derive(Debug, Clap)]
+struct ConvertOpts {
+    #[clap(
+        long,
+        global = true,
+        about = "Preset of conversion options defined in the config"
+    )]
+    preset: Option<String>,
+    #[clap(long, global = true, about = "Output format: xml, json or nvcomment")]
+    format: Option<Format>,
+    #[clap(
+        long,
+        global = true,
+        about = "Directory where output files are written"
+    )]
+    output_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        global = true,
+        about = "Thread ID of output files (defaults to UNIX time at vpos 0)"
+    )]
+    thread: Option<String>,
+    #[clap(long, global = true, about = "Video ID of output files like sm9")]
+    video_id: Option<String>,
+    #[clap(
+        long,
+        global = true,
+        about = "Time at vpos 0 (defaults to the first tweet)"
+    )]
+    anchor: Option<String>,
+    #[clap(
+        long,
+        global = true,
+        about = "Timezone of dates and times like Asia/Tokyo (defaults to UTC)"
+    )]
+    tz: Option<Tz>,
+    #[clap(
+        long,
+        global = true,
+        allow_hyphen_values = true,
+        parse(try_from_str = shift::parse_time),
+        about = "Seconds added to vpos like -2.5 or 1:30 (whole seconds for tweets)"
+    )]
+    offset: Option<f64>,
+    #[clap(
+        long,
+        global = true,
+        about = "Maximum number of comments within a second"
+    )]
+    density: Option<usize>,
+    #[clap(
+        long = "ng",
+        global = true,
+        number_of_values = 1,
+        about = "Drops comments containing the word"
+    )]
+    ng_words: Vec<String>,
+    #[clap(
+        long = "ng-user",
+        global = true,
+        number_of_values = 1,
+        about = "Drops comments from the user"
+    )]
+    ng_users: Vec<String>,
+    #[clap(long, global = true, about = "Keeps hashtags in comments")]
+    keep_hashtags: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "keep-hashtags",
+        about = "Removes hashtags from comments"
+    )]
+    strip_hashtags: bool,
+    #[clap(long, global = true, about = "Keeps URLs in comments")]
+    keep_urls: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "keep-urls",
+        about = "Removes URLs from comments"
+    )]
+    strip_urls: bool,
+    #[clap(long, global = true, about = "Removes mentions from comments")]
+    strip_mentions: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "strip-mentions",
+        about = "Keeps mentions in comments"
+    )]
+    keep_mentions: bool,
+    #[clap(
+        long,
+        global = true,
+        about = "Adds placeholders like [画像] for attached media"
+    )]
+    media: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "media",
+        about = "Adds no media placeholders"
+    )]
+    no_media: bool,
+    #[clap(long, global = true, about = "Adds alt text of attached images")]
+    alt_text: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "alt-text",
+        about = "Adds no alt text"
+    )]
+    no_alt_text: bool,
+    #[clap(long, global = true, about = "Adds text of quoted tweets")]
+    quoted: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "quoted",
+        about = "Adds no text of quoted tweets"
+    )]
+    no_quoted: bool,
+    #[clap(long, global = true, about = "Adds titles of link cards")]
+    cards: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "cards",
+        about = "Adds no titles of link cards"
+    )]
+    no_cards: bool,
+    #[clap(
+        long,
+        global = true,
+        about = "Drops replies whose parent is not in the search results"
+    )]
+    drop_orphans: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "drop-orphans",
+        about = "Keeps orphan replies"
+    )]
+    no_drop_orphans: bool,
+    #[clap(
+        long,
+        global = true,
+        about = "Prefixes replies with up to this number of characters of the parent like \"> parent\" (0 disables)"
+    )]
+    quote_parent: Option<usize>,
+    #[clap(long, global = true, about = "Places replies just after their parent")]
+    follow_parent: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "follow-parent",
+        about = "Places replies at their own time"
+    )]
+    no_follow_parent: bool,
+    #[clap(
+        long = "lang",
+        global = true,
+        use_delimiter = true,
+        about = "Keeps only tweets in the languages like ja,en"
+    )]
+    langs: Vec<String>,
+    #[clap(
+        long,
+        global = true,
+        about = "Styles tweets in other languages with commands like \"small gray\" instead of dropping them"
+    )]
+    lang_style: Option<String>,
+    #[clap(
+        long,
+        global = true,
+        about = "Replaces user IDs with opaque IDs stable within a file"
+    )]
+    anonymize: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "anonymize",
+        about = "Keeps user IDs as they are"
+    )]
+    no_anonymize: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "anonymize",
+        about = "Removes user and tweet IDs"
+    )]
+    strip_ids: bool,
+    #[clap(
+        long,
+        global = true,
+        conflicts_with = "strip-ids",
+        about = "Keeps user and tweet IDs"
+    )]
+    no_strip_ids: bool,
+    #[clap(
+        long,
+        global = true,
+        about = "Template of comments with authors using {text}, {name} and {screen_name} (empty disables)"
+    )]
+    attribution: Option<String>,
+    #[clap(
+        long = "attribute",
+        global = true,
+        number_of_values = 1,
+        about = "Attributes only tweets from the account"
+    )]
+    attributed: Vec<String>,
+    #[clap(
+        long = "owner",
+        global = true,
+        number_of_values = 1,
+        about = "Makes tweets from the account owner comments"
+    )]
+    owners: Vec<String>,
+}
+
+impl ConvertOpts {
+    /// Options by flags over the preset and defaults in the config.
+    fn resolve(&self, cfg: &config::Config) -> MainResult<Preset> {
+        Ok(cfg.preset(self.preset.as_deref())?.merge(self.preset()))
+    }
+
+    fn preset(&self) -> Preset {
+        Preset {
+            output_dir: self.output_dir.clone(),
+            timezone: self.tz,
+            format: self.format,
+            filename: None,
+            thread: self.thread.clone(),
+            video_id: self.video_id.clone(),
+            offset: self.offset.map(|o| o.round() as i64),
+            density: self.density,
+            ng_words: self.ng_words.clone(),
+            ng_users: self.ng_users.clone(),
+            strip_hashtags: flag(self.strip_hashtags, self.keep_hashtags),
+            strip_urls: flag(self.strip_urls, self.keep_urls),
+            strip_mentions: flag(self.strip_mentions, self.keep_mentions),
+            media: flag(self.media, self.no_media),
+            alt_text: flag(self.alt_text, self.no_alt_text),
+            quoted: flag(self.quoted, self.no_quoted),
+            cards: flag(self.cards, self.no_cards),
+            drop_orphans: flag(self.drop_orphans, self.no_drop_orphans),
+            quote_parent: self.quote_parent,
+            follow_parent: flag(self.follow_parent, self.no_follow_parent),
+            langs: self.langs.clone(),
+            lang_style: self.lang_style.clone(),
+            anonymize: flag(self.anonymize, self.no_anonymize),
+            strip_ids: flag(self.strip_ids, self.no_strip_ids),
+            attribution: self.attribution.clone(),
+            attributed: self.attributed.clone(),
+            owners: self.owners.clone(),
+        }
+    }
+}
+
+/// A pair of flags to turn an option on or off, which is left to the preset without both.
+fn flag(on: bool, off: bool) -> Option<bool> {
+    if on {
+        Some(true)
+    } else if off {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clap)]
+enum Command {
+    #[clap(about = "Manages auth profiles")]
+    Profile(ProfileCommand),
+    #[clap(about = "Changes where secrets of profiles are stored")]
+    Secrets(SecretsCommand),
+    #[clap(about = "Runs jobs in a TOML or JSON manifest")]
+    Batch {
+        manifest: PathBuf,
+        #[clap(
+            long,
+            short,
+            default_value = "1",
+            about = "Number of jobs run at the same time"
+        )]
+        jobs: usize,
+        #[clap(
+            long,
+            number_of_values = 1,
+            about = "Runs only the jobs with the names or numbers like #2"
+        )]
+        only: Vec<String>,
+    },
+    #[clap(about = "Summarizes a tweet archive saved by --archive or an XML file")]
+    Stats {
+        input: PathBuf,
+        #[clap(long, about = "Prints as JSON")]
+        json: bool,
+        #[clap(
+            long,
+            default_value = "10",
+            about = "Number of entries in top lists and peaks"
+        )]
+        top: usize,
+    },
+    #[clap(about = "Merges tweet archives and XML files into one comment file")]
+    Merge {
+        #[clap(setting = ArgSettings::Required)]
+        inputs: Vec<PathBuf>,
+        #[clap(long, short, about = "Output file")]
+        output: PathBuf,
+        #[clap(
+            long,
+            about = "Recalculates vpos from dates with --anchor or the first comment"
+        )]
+        realign: bool,
+    },
+    #[clap(about = "Shifts, scales or remaps vpos of comments in an XML file")]
+    Shift {
+        input: PathBuf,
+        #[clap(long, short, about = "Output file")]
+        output: PathBuf,
+        #[clap(
+            long,
+            default_value = "1",
+            about = "Factor multiplied to vpos like 1.001 for framerate differences"
+        )]
+        scale: f64,
+        #[clap(
+            long = "map",
+            number_of_values = 1,
+            allow_hyphen_values = true,
+            about = "Moves comments in FROM-TO by OFFSET like 1:30-10:00=-30, dropping comments out of all segments"
+        )]
+        segments: Vec<Segment>,
+    },
+}
+
+#[derive(Debug, Clap)]
+enum ProfileCommand {
+    #[clap(about = "Lists profiles")]
+    List,
+    #[clap(about = "Adds a profile or updates its auth information")]
+    Add {
+        name: String,
+        #[clap(long, about = "Imports auth cookies from a cookies.txt or HAR file")]
+        import_cookies: Option<String>,
+    },
+    #[clap(about = "Removes a profile")]
+    Remove { name: String },
+    #[clap(about = "Sets the profile used by default")]
+    Default { name: String },
+}
+
+#[derive(Debug, Clap)]
+enum SecretsCommand {
+    #[clap(about = "Stores secrets in the config file as plain text")]
+    Plain,
+    #[clap(about = "Stores secrets in a file encrypted with a passphrase")]
+    Encrypted {
+        #[clap(long, about = "Defaults to secrets.enc in the config directory")]
+        path: Option<PathBuf>,
+    },
+    #[clap(about = "Reads and writes secrets in the given file")]
+    File { path: PathBuf },
 }
 
 #[tokio::main]
-async fn main() -> MainResult<()> {
-    let opts = Opts::parse();
-    let output = opts
-        .output
-        .as_ref()
-        .map(|o| o.to_string())
-        .unwrap_or_else(|| format!("{}_{}_{}.xml", &opts.text, &opts.since, &opts.until));
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("error: {}", e);
+        exit(1);
+    }
+}
+
+async fn run() -> MainResult<()> {
+    let mut opts = Opts::parse();
+    logging::init(opts.verbose, opts.log_file.as_deref())?;
+    let interactive = !opts.no_interactive;
 
-    let mut settings = config::Config::load()?;
-    if opts.reset || !settings.init || !settings.validate() {
-        settings = init()?;
+    let mut cfg = config::Config::load(interactive)?;
+    match opts.cmd.take() {
+        Some(Command::Profile(cmd)) => {
+            cfg.unlock()?;
+            profile(cmd, cfg).await
+        }
+        Some(Command::Secrets(cmd)) => {
+            cfg.unlock()?;
+            secrets(cmd, cfg)
+        }
+        Some(Command::Batch {
+            manifest,
+            jobs,
+            only,
+        }) => batch(&opts, cfg, &manifest, jobs, &only).await,
+        Some(Command::Stats { input, json, top }) => stats(&opts, &cfg, &input, json, top),
+        Some(Command::Merge {
+            inputs,
+            output,
+            realign,
+        }) => merge(&opts, &cfg, &inputs, &output, realign),
+        Some(Command::Shift {
+            input,
+            output,
+            scale,
+            segments,
+        }) => shift(
+            &opts,
+            &cfg,
+            &input,
+            &output,
+            Retime {
+                segments,
+                scale,
+                // only the flag, since offsets of presets are for converting tweets
+                offset: opts.convert.offset.unwrap_or(0.0),
+            },
+        ),
+        None => search(opts, cfg).await,
     }
-    let settings = settings;
+}
 
-    let client = TwitterClient::new(settings).map_err(|_| MainError::Auth)?;
-    let query = Query {
-        text: opts.text.to_string(),
-        since: Some(opts.since),
-        until: Some(opts.until),
+async fn search(opts: Opts, mut cfg: config::Config) -> MainResult<()> {
+    // required unless a subcommand is given
+    let (since, until) = match (opts.since.as_ref(), opts.until.as_ref()) {
+        (Some(since), Some(until)) => (since, until),
+        _ => unreachable!(),
+    };
+    let job = Job {
+        queries: opts.queries.clone(),
+        since: since.to_string(),
+        until: until.to_string(),
+        anchor: opts.convert.anchor.clone(),
+        episode: None,
+        preset: opts.convert.resolve(&cfg)?,
+        output: opts.output.clone(),
+        archive: opts.archive.clone(),
     };
+    job.validate()?;
 
-    // TODO: write json async, and converting into XML will be executed after writing JSON
-    let tweets = client
-        .search_tweets(query)
-        .inspect_ok(|r| {
-            if r.len() > 0 {
+    let client = client(&opts, &mut cfg).await?;
+    let outcome = job.run(&client, opts.log_mode(), None).await?;
+    match opts.log_mode() {
+        Mode::Quiet => {}
+        Mode::Json => eprintln!(
+            "{}",
+            serde_json::json!({
+                "event": "done",
+                "output": outcome.output,
+                "count": outcome.count,
+                "trimmed": outcome.trimmed,
+            })
+        ),
+        Mode::Text | Mode::Lines => {
+            if outcome.trimmed > 0 {
                 eprintln!(
-                    "{} tweets: {}/{} {} {}",
-                    r.len(),
-                    r.first()
-                        .and_then(|t| t.user.as_ref())
-                        .map(|u| &u.screen_name as &str)
-                        .unwrap_or(""),
-                    r.first().map(|t| &t.id as &str).unwrap_or(""),
-                    r.first()
-                        .and_then(|t| t.created_at)
-                        .map(|d| d.to_rfc3339())
-                        .unwrap_or_default(),
-                    r.first()
-                        .map(|t| t
-                            .full_text
-                            .chars()
-                            .take(20)
-                            .collect::<String>()
-                            .replace("\n", ""))
-                        .unwrap_or_default()
+                    "{} tweets outside the time window are trimmed.",
+                    outcome.trimmed
                 );
             }
-        })
-        .try_collect::<Vec<_>>()
-        .await?;
+            match outcome.output {
+                Some(_) => eprintln!("{} tweets are saved!", outcome.count),
+                None => eprintln!("No tweet found."),
+            }
+        }
+    }
+    Ok(())
+}
 
-    let tweets = tweets
-        .into_iter()
-        .flatten()
-        .collect::<Vec<_>>()
+/// Reads chats from an XML file, or tweets from an archive.
+fn read_source(input: &Path) -> MainResult<Source> {
+    let r = BufReader::new(File::open(input)?);
+    Ok(if input.extension() == Some(OsStr::new("xml")) {
+        Source::Chats(read_xml(r)?.0)
+    } else {
+        Source::Tweets(serde_json::from_reader(r)?)
+    })
+}
+
+/// Reads chats from an XML file, or converts tweets in an archive with the options.
+fn read_chats(input: &Path, options: ConvertOptions) -> MainResult<Vec<Chat>> {
+    Ok(match read_source(input)? {
+        Source::Chats(c) => c,
+        Source::Tweets(t) => t.into_iter().map_to_sorted_chats_with(options).collect(),
+    })
+}
+
+/// Parses `--anchor` in the timezone of the preset.
+fn anchor(opts: &Opts, preset: &Preset) -> MainResult<Option<DateTime<Utc>>> {
+    Ok(opts
+        .convert
+        .anchor
+        .as_ref()
+        .map(|a| datetime::parse(a, preset.timezone.unwrap_or(Tz::UTC)))
+        .transpose()?)
+}
+
+fn stats(
+    opts: &Opts,
+    cfg: &config::Config,
+    input: &Path,
+    json: bool,
+    top: usize,
+) -> MainResult<()> {
+    let preset = opts.convert.resolve(cfg)?;
+    // keep texts as they are to count hashtags and retweets
+    let options = ConvertOptions {
+        cleanup: Cleanup {
+            hashtags: false,
+            urls: false,
+            mentions: false,
+        },
+        ..preset.convert_options(anchor(opts, &preset)?)
+    };
+    let chats = read_chats(input, options)?;
+
+    let stats = Stats::new(&chats, top);
+    let stdout = stdout();
+    let mut out = stdout.lock();
+    if json {
+        serde_json::to_writer_pretty(&mut out, &stats)?;
+        writeln!(out)?;
+    } else {
+        stats.write(&mut out)?;
+    }
+    Ok(())
+}
+
+fn merge(
+    opts: &Opts,
+    cfg: &config::Config,
+    inputs: &[PathBuf],
+    output: &Path,
+    realign: bool,
+) -> MainResult<()> {
+    let preset = opts.convert.resolve(cfg)?;
+    let anchor = anchor(opts, &preset)?;
+    let align = if realign {
+        Align::Realign
+    } else {
+        Align::Preserve
+    };
+
+    let sources = inputs
+        .iter()
+        .map(|i| read_source(i))
+        .collect::<MainResult<Vec<_>>>()?;
+    let chats = merge::merge(sources, preset.convert_options(anchor), align)
         .into_iter()
-        .map_to_sorted_chats()
+        .filter_chats(preset.filter())
+        .anonymize(preset.privacy())
         .collect::<Vec<_>>();
 
-    if tweets.is_empty() {
-        eprintln!("No tweet found.");
-        return Ok(());
+    if let Some(dir) = output.parent() {
+        create_dir_all(dir)?;
+    }
+    let info = preset.thread_info(
+        anchor
+            .map(|a| a.timestamp())
+            .or_else(|| chats.first().map(|c| c.date as i64 - c.vpos as i64 / 100)),
+    );
+    preset.format.unwrap_or_default().write(
+        BufWriter::new(File::create(output)?),
+        &chats,
+        &info,
+    )?;
+    if opts.log_mode().is_text() {
+        eprintln!("{} comments are saved!", chats.len());
     }
+    Ok(())
+}
 
-    let tweets = spawn_blocking(move || -> MainResult<Vec<Chat>> {
-        let w = File::create(output)?;
-        write_xml(w, tweets.iter())?;
-        Ok(tweets)
-    })
-    .await??;
+fn shift(
+    opts: &Opts,
+    cfg: &config::Config,
+    input: &Path,
+    output: &Path,
+    retime: Retime,
+) -> MainResult<()> {
+    let preset = opts.convert.resolve(cfg)?;
+    let format = preset.format.unwrap_or_default();
+    let (mut chats, info) = read_xml(BufReader::new(File::open(input)?))?;
+    // chats refer to the thread of the file, which is written back unless another one is given
+    let info = match info {
+        Some(i) if preset.thread.is_none() => ThreadInfo {
+            video_id: preset.video_id.clone().or(i.video_id),
+            ..i
+        },
+        _ => {
+            chats.iter_mut().for_each(|c| c.thread = None);
+            preset.thread_info(chats.first().map(|c| c.date as i64 - c.vpos as i64 / 100))
+        }
+    };
+    let total = chats.len();
+    let chats = retime.apply(chats);
+
+    if let Some(dir) = output.parent() {
+        create_dir_all(dir)?;
+    }
+    format.write(BufWriter::new(File::create(output)?), &chats, &info)?;
+    if opts.log_mode().is_text() {
+        eprintln!(
+            "{} comments are saved, and {} are dropped.",
+            chats.len(),
+            total - chats.len()
+        );
+    }
+    Ok(())
+}
+
+async fn batch(
+    opts: &Opts,
+    mut cfg: config::Config,
+    manifest: &Path,
+    parallel: usize,
+    only: &[String],
+) -> MainResult<()> {
+    let manifest = Manifest::load(manifest)?;
+    let client = client(opts, &mut cfg).await?;
+    batch::run(
+        manifest,
+        &client,
+        &cfg,
+        &opts.convert.preset(),
+        opts.log_mode(),
+        parallel,
+        only,
+    )
+    .await
+}
 
-    eprintln!("{} tweets are saved!", tweets.len());
+/// Creates a client from auth information in environment variables or the profile.
+async fn client(opts: &Opts, cfg: &mut config::Config) -> MainResult<TwitterClient> {
+    let cookie = match opts.cookie_file.as_ref() {
+        Some(f) => Some(Cookie::import(&read_to_string(f)?)?),
+        None => None,
+    };
+    let overrides = config::Overrides::from_env(cookie)?;
+
+    if let Some(p) = overrides.profile() {
+        info!("using auth information from environment variables");
+        TwitterClient::new(p).map_err(|e| MainError::Auth("environment variables".to_string(), e))
+    } else {
+        cfg.unlock()?;
+        let name = cfg.profile_name(opts.profile.as_deref()).to_string();
+        info!(profile = %name, "using a profile");
+        let mut p = overrides.apply(cfg.profiles.get(&name).cloned().unwrap_or_default());
+        if opts.reset || opts.import_cookies.is_some() || !p.is_valid() {
+            if opts.no_interactive {
+                return Err(MainError::NoAuth(name));
+            }
+            p = init(opts.import_cookies.as_deref()).await?;
+            cfg.profiles.insert(name.clone(), p.clone());
+            cfg.store()?;
+        }
+
+        let mut names = vec![name];
+        let mut auths = vec![p];
+        if opts.rotate {
+            let (n, a): (Vec<_>, Vec<_>) = cfg.rotation(&names[0]).into_iter().unzip();
+            names.extend(n);
+            auths.extend(a);
+        }
+        TwitterClient::with_auths(auths).map_err(|e| {
+            let i = match e {
+                AuthError::Header(i, _) => i,
+                AuthError::Empty => 0,
+            };
+            MainError::Auth(format!("profile {}", names[i]), e)
+        })
+    }
+}
+
+async fn profile(cmd: ProfileCommand, mut cfg: config::Config) -> MainResult<()> {
+    match cmd {
+        ProfileCommand::List => {
+            let default = cfg.profile_name(None);
+            for (name, p) in &cfg.profiles {
+                println!(
+                    "{} {}{}",
+                    if name == default { "*" } else { " " },
+                    name,
+                    if p.is_valid() { "" } else { " (invalid)" }
+                );
+            }
+            return Ok(());
+        }
+        ProfileCommand::Add {
+            name,
+            import_cookies,
+        } => {
+            let p = init(import_cookies.as_deref()).await?;
+            cfg.profiles.insert(name, p);
+        }
+        ProfileCommand::Remove { name } => {
+            cfg.profiles
+                .remove(&name)
+                .ok_or_else(|| MainError::ProfileNotFound(name.to_string()))?;
+            if cfg.default_profile.as_ref() == Some(&name) {
+                cfg.default_profile = None;
+            }
+        }
+        ProfileCommand::Default { name } => {
+            if !cfg.profiles.contains_key(&name) {
+                return Err(MainError::ProfileNotFound(name));
+            }
+            cfg.default_profile = Some(name);
+        }
+    }
+    cfg.store()?;
+    Ok(())
+}
+
+fn secrets(cmd: SecretsCommand, mut cfg: config::Config) -> MainResult<()> {
+    cfg.set_storage(match cmd {
+        SecretsCommand::Plain => Storage::Plain,
+        SecretsCommand::Encrypted { path } => Storage::Encrypted { path },
+        SecretsCommand::File { path } => Storage::File { path },
+    })?;
     Ok(())
 }
 
-fn init() -> MainResult<config::Config> {
+async fn init(cookie_file: Option<&str>) -> MainResult<config::Profile> {
     eprintln!("Please provide Twitter auth information!");
 
     let theme = ColorfulTheme {
@@ -134,12 +910,57 @@ fn init() -> MainResult<config::Config> {
     let authorization_token = Input::with_theme(&theme)
         .with_prompt("Authorization bearer token")
         .interact()?;
-    let csrf_token = Input::with_theme(&theme)
-        .with_prompt("CSRF token")
-        .interact()?;
-    let cookie = Input::with_theme(&theme).with_prompt("Cookie").interact()?;
+    let cookie = if let Some(cookie_file) = cookie_file {
+        Cookie::import(&read_to_string(cookie_file)?)?
+    } else {
+        Input::<String>::with_theme(&theme)
+            .with_prompt("Cookie")
+            .interact()?
+            .parse()?
+    };
 
-    let cfg = config::Config::from_cookie(authorization_token, csrf_token, cookie)?;
-    cfg.store()?;
-    Ok(cfg)
+    let p = config::Profile::from_cookie(authorization_token, cookie);
+    TwitterClient::new(p.clone())
+        .map_err(|e| MainError::Auth("input".to_string(), e))?
+        .verify()
+        .await
+        .map_err(MainError::Verify)?;
+    Ok(p)
+}
+
+#[test]
+fn test_global_flags() {
+    let opts = Opts::try_parse_from([
+        "twinicodo",
+        "merge",
+        "a.xml",
+        "b.json",
+        "-o",
+        "out.xml",
+        "--anchor",
+        "2020-01-01 00:00",
+        "--realign",
+        "--anonymize",
+        "-v",
+    ])
+    .unwrap();
+    assert_eq!(Some("2020-01-01 00:00"), opts.convert.anchor.as_deref());
+    assert!(opts.convert.anonymize);
+    assert_eq!(1, opts.verbose);
+    assert!(matches!(
+        opts.cmd,
+        Some(Command::Merge { realign: true, .. })
+    ));
+
+    let opts = Opts::try_parse_from([
+        "twinicodo",
+        "shift",
+        "a.xml",
+        "-o",
+        "b.xml",
+        "--offset",
+        "-2.5",
+    ])
+    .unwrap();
+    assert_eq!(Some(-2.5), opts.convert.offset);
 }
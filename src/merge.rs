@@ -0,0 +1,228 @@
+use chrono::{TimeZone, Utc};
+use std::collections::HashSet;
+use twinicodo::{
+    iter::{ConvertOptions, SortedTweetToChat},
+    nicodo::Chat,
+    twitter::Tweet,
+};
+
+/// Input file of merging.
+#[derive(Debug)]
+pub enum Source {
+    /// Chats read from a file, which already have vpos
+    Chats(Vec<Chat>),
+    /// Tweets in an archive, which are converted before merging
+    Tweets(Vec<Tweet>),
+}
+
+impl Source {
+    /// UNIX time of the earliest chat or tweet.
+    fn earliest(&self) -> Option<i64> {
+        match self {
+            Source::Chats(c) => c.iter().map(|c| c.date as i64).min(),
+            Source::Tweets(t) => t
+                .iter()
+                .filter_map(|t| t.created_at)
+                .map(|d| d.timestamp())
+                .min(),
+        }
+    }
+}
+
+/// How vpos of merged chats is decided.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    /// Keeps vpos of chats in files, adding the offset of the options.
+    /// Tweets of all archives are converted from the anchor of the options,
+    /// or the earliest tweet across every archive.
+    Preserve,
+    /// Recalculates vpos of all chats from dates with the anchor of the options,
+    /// or the earliest chat or tweet across every source.
+    Realign,
+}
+
+/// Combines chats from several sources into one timeline sorted by vpos.
+/// Chats are deduplicated by IDs, or by dates, users and texts when either of them has no ID.
+/// Chats which get negative vpos are dropped, and thread IDs of sources are cleared
+/// since merged chats make a new thread.
+pub fn merge(sources: Vec<Source>, options: ConvertOptions, align: Align) -> Vec<Chat> {
+    let anchor = options.anchor.map(|d| d.timestamp()).or_else(|| {
+        sources
+            .iter()
+            .filter(|s| align == Align::Realign || matches!(s, Source::Tweets(_)))
+            .filter_map(Source::earliest)
+            .min()
+    });
+    let offset = options.offset;
+    let options = ConvertOptions {
+        anchor: anchor.map(|a| Utc.timestamp(a, 0)),
+        ..options
+    };
+
+    let mut ids = HashSet::new();
+    // dates, users and texts of chats with IDs and without, compared when either lacks an ID
+    let (mut with_ids, mut without_ids) = (HashSet::new(), HashSet::new());
+    let mut chats = sources
+        .into_iter()
+        .flat_map(|s| match s {
+            Source::Chats(c) if align == Align::Preserve => c
+                .into_iter()
+                .filter_map(|c| {
+                    let vpos = c.vpos as i64 + offset * 100;
+                    if vpos < 0 {
+                        return None;
+                    }
+                    Some(Chat {
+                        vpos: vpos as u64,
+                        ..c
+                    })
+                })
+                .collect(),
+            Source::Chats(c) => c,
+            Source::Tweets(t) => t
+                .into_iter()
+                .map_to_sorted_chats_with(options.clone())
+                .collect(),
+        })
+        .filter(|c| {
+            let key = (c.date, c.user_id.clone(), c.content.clone());
+            match c.id.as_ref() {
+                Some(id) => {
+                    if without_ids.contains(&key) || !ids.insert(id.to_string()) {
+                        return false;
+                    }
+                    with_ids.insert(key);
+                    true
+                }
+                None => !with_ids.contains(&key) && without_ids.insert(key),
+            }
+        })
+        .map(|c| Chat { thread: None, ..c })
+        .collect::<Vec<_>>();
+
+    if let (Align::Realign, Some(anchor)) = (align, anchor) {
+        chats = chats
+            .into_iter()
+            .filter_map(|c| {
+                let vpos = (c.date as i64 - anchor + offset) * 100;
+                if vpos < 0 {
+                    return None;
+                }
+                Some(Chat {
+                    vpos: vpos as u64,
+                    ..c
+                })
+            })
+            .collect();
+    }
+    // density filters expect chats in order of vpos
+    chats.sort_by_key(|c| (c.vpos, c.date));
+    chats
+}
+
+#[test]
+fn test_merge() {
+    let chat = |date: u64, vpos: u64, id: Option<&str>, content: &str| Chat {
+        date,
+        vpos,
+        user_id: Some("a".to_string()),
+        id: id.map(|s| s.to_string()),
+        mail: None,
+        content: content.to_string(),
+        ..Chat::default()
+    };
+    let sources = || {
+        vec![
+            Source::Chats(vec![chat(10, 0, Some("1"), "a"), chat(30, 2000, None, "c")]),
+            Source::Chats(vec![
+                chat(20, 500, Some("2"), "b"),
+                chat(10, 0, Some("1"), "a"),
+            ]),
+            Source::Chats(vec![chat(30, 2000, None, "c"), chat(5, 0, None, "d")]),
+        ]
+    };
+
+    let merged = merge(sources(), ConvertOptions::default(), Align::Preserve);
+    assert_eq!(
+        vec![("d", 0), ("a", 0), ("b", 500), ("c", 2000)],
+        merged
+            .iter()
+            .map(|c| (c.content.as_str(), c.vpos))
+            .collect::<Vec<_>>()
+    );
+
+    let options = ConvertOptions {
+        anchor: Some(Utc.timestamp(10, 0)),
+        ..ConvertOptions::default()
+    };
+    let merged = merge(sources(), options, Align::Realign);
+    assert_eq!(
+        vec![("a", 0), ("b", 1000), ("c", 2000)],
+        merged
+            .iter()
+            .map(|c| (c.content.as_str(), c.vpos))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_merge_archives() {
+    let tweet = |id: &str, date: i64| Tweet {
+        id: id.to_string(),
+        created_at: Some(Utc.timestamp(date, 0)),
+        full_text: id.to_string(),
+        ..Tweet::default()
+    };
+    let sources = || {
+        vec![
+            Source::Tweets(vec![tweet("a", 100), tweet("b", 110)]),
+            Source::Tweets(vec![tweet("c", 120), tweet("d", 130)]),
+            Source::Chats(vec![
+                Chat {
+                    date: 90,
+                    vpos: 500,
+                    content: "e".to_string(),
+                    ..Chat::default()
+                },
+                // converted from the tweet in another file
+                Chat {
+                    date: 110,
+                    vpos: 1500,
+                    content: "b".to_string(),
+                    ..Chat::default()
+                },
+            ]),
+        ]
+    };
+    let vpos = |chats: Vec<Chat>| {
+        chats
+            .into_iter()
+            .map(|c| (c.content, c.vpos))
+            .collect::<Vec<_>>()
+    };
+    let options = ConvertOptions {
+        offset: 5,
+        ..ConvertOptions::default()
+    };
+
+    assert_eq!(
+        vec![
+            ("a".to_string(), 500),
+            ("e".to_string(), 1000),
+            ("b".to_string(), 1500),
+            ("c".to_string(), 2500),
+            ("d".to_string(), 3500),
+        ],
+        vpos(merge(sources(), options.clone(), Align::Preserve))
+    );
+    assert_eq!(
+        vec![
+            ("e".to_string(), 500),
+            ("a".to_string(), 1500),
+            ("b".to_string(), 2500),
+            ("c".to_string(), 3500),
+            ("d".to_string(), 4500),
+        ],
+        vpos(merge(sources(), options, Align::Realign))
+    );
+}
@@ -0,0 +1,212 @@
+use crate::format::Format;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use twinicodo::{
+    anonymize::Privacy,
+    filter::Filter,
+    iter::{Attribution, Cleanup, ConvertOptions, Extras, Threading},
+    lang::LangFilter,
+    nicodo::ThreadInfo,
+};
+
+/// Conversion settings saved as defaults or named presets in the config.
+/// Unset values fall back to the lower layer: flags, a preset and then defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    pub output_dir: Option<PathBuf>,
+    /// Timezone of dates and times without an offset
+    pub timezone: Option<Tz>,
+    pub format: Option<Format>,
+    /// Template of output filenames
+    pub filename: Option<String>,
+    /// Thread ID of output files. UNIX time at vpos 0 is used when not specified.
+    pub thread: Option<String>,
+    /// Video ID of output files like `sm9`
+    pub video_id: Option<String>,
+    /// Seconds added to vpos of all comments
+    pub offset: Option<i64>,
+    /// Maximum number of comments within a second
+    pub density: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ng_words: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ng_users: Vec<String>,
+    pub strip_hashtags: Option<bool>,
+    pub strip_urls: Option<bool>,
+    pub strip_mentions: Option<bool>,
+    /// Adds placeholders like `[画像]` for attached media
+    pub media: Option<bool>,
+    /// Adds alt text of attached images
+    pub alt_text: Option<bool>,
+    /// Adds text of quoted tweets
+    pub quoted: Option<bool>,
+    /// Adds titles of link cards
+    pub cards: Option<bool>,
+    /// Drops replies whose parent is not in the search results
+    pub drop_orphans: Option<bool>,
+    /// Prefixes replies with the parent text like `> parent` up to this number of characters.
+    /// `0` disables it.
+    pub quote_parent: Option<usize>,
+    /// Places replies just after their parent in vpos
+    pub follow_parent: Option<bool>,
+    /// Languages of tweets like `ja`. Tweets in any languages are kept when empty.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub langs: Vec<String>,
+    /// Commands like `small gray` for tweets in other languages instead of dropping them
+    pub lang_style: Option<String>,
+    /// Replaces user IDs with opaque IDs in output files
+    pub anonymize: Option<bool>,
+    /// Removes user and tweet IDs from output files
+    pub strip_ids: Option<bool>,
+    /// Template of comments with authors like `{text} ({name})`. An empty one disables it.
+    pub attribution: Option<String>,
+    /// Accounts attributed by the template. All accounts are when empty.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attributed: Vec<String>,
+    /// Accounts whose tweets become owner comments
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<String>,
+}
+
+impl Preset {
+    /// Values of `other` take precedence, and lists are concatenated
+    /// except languages, which are replaced when `other` has any.
+    pub fn merge(self, other: Preset) -> Self {
+        Self {
+            output_dir: other.output_dir.or(self.output_dir),
+            timezone: other.timezone.or(self.timezone),
+            format: other.format.or(self.format),
+            filename: other.filename.or(self.filename),
+            thread: other.thread.or(self.thread),
+            video_id: other.video_id.or(self.video_id),
+            offset: other.offset.or(self.offset),
+            density: other.density.or(self.density),
+            ng_words: self.ng_words.into_iter().chain(other.ng_words).collect(),
+            ng_users: self.ng_users.into_iter().chain(other.ng_users).collect(),
+            strip_hashtags: other.strip_hashtags.or(self.strip_hashtags),
+            strip_urls: other.strip_urls.or(self.strip_urls),
+            strip_mentions: other.strip_mentions.or(self.strip_mentions),
+            media: other.media.or(self.media),
+            alt_text: other.alt_text.or(self.alt_text),
+            quoted: other.quoted.or(self.quoted),
+            cards: other.cards.or(self.cards),
+            drop_orphans: other.drop_orphans.or(self.drop_orphans),
+            quote_parent: other.quote_parent.or(self.quote_parent),
+            follow_parent: other.follow_parent.or(self.follow_parent),
+            langs: if other.langs.is_empty() {
+                self.langs
+            } else {
+                other.langs
+            },
+            lang_style: other.lang_style.or(self.lang_style),
+            anonymize: other.anonymize.or(self.anonymize),
+            strip_ids: other.strip_ids.or(self.strip_ids),
+            attribution: other.attribution.or(self.attribution),
+            attributed: self
+                .attributed
+                .into_iter()
+                .chain(other.attributed)
+                .collect(),
+            owners: self.owners.into_iter().chain(other.owners).collect(),
+        }
+    }
+
+    pub fn convert_options(&self, anchor: Option<DateTime<Utc>>) -> ConvertOptions {
+        let cleanup = Cleanup::default();
+        ConvertOptions {
+            anchor,
+            offset: self.offset.unwrap_or(0),
+            cleanup: Cleanup {
+                hashtags: self.strip_hashtags.unwrap_or(cleanup.hashtags),
+                urls: self.strip_urls.unwrap_or(cleanup.urls),
+                mentions: self.strip_mentions.unwrap_or(cleanup.mentions),
+            },
+            extras: Extras {
+                media: self.media.unwrap_or(false),
+                alt_text: self.alt_text.unwrap_or(false),
+                quoted: self.quoted.unwrap_or(false),
+                cards: self.cards.unwrap_or(false),
+            },
+            threading: Threading {
+                drop_orphans: self.drop_orphans.unwrap_or(false),
+                quote_parent: self.quote_parent.filter(|&n| n > 0),
+                follow_parent: self.follow_parent.unwrap_or(false),
+            },
+            languages: if self.langs.is_empty() {
+                None
+            } else {
+                Some(LangFilter {
+                    langs: self.langs.clone(),
+                    mail: self.lang_style.clone(),
+                })
+            },
+            owners: self.owners.clone(),
+            attribution: self
+                .attribution
+                .as_ref()
+                .filter(|t| !t.is_empty())
+                .map(|template| Attribution {
+                    template: template.to_string(),
+                    accounts: self.attributed.clone(),
+                }),
+        }
+    }
+
+    /// Metadata of an output file whose vpos 0 is at `anchor` in UNIX time.
+    pub fn thread_info(&self, anchor: Option<i64>) -> ThreadInfo {
+        let info = ThreadInfo::default();
+        ThreadInfo {
+            thread: self
+                .thread
+                .clone()
+                .or_else(|| anchor.map(|a| a.to_string()))
+                .unwrap_or(info.thread),
+            video_id: self.video_id.clone(),
+            ..info
+        }
+    }
+
+    pub fn filter(&self) -> Filter {
+        Filter {
+            ng_words: self.ng_words.clone(),
+            ng_users: self.ng_users.clone(),
+            density: self.density,
+        }
+    }
+
+    /// Anonymization uses a new random salt for each call, which should be once per file.
+    pub fn privacy(&self) -> Privacy {
+        if self.strip_ids.unwrap_or(false) {
+            Privacy::Strip
+        } else if self.anonymize.unwrap_or(false) {
+            Privacy::Anonymize {
+                salt: base64::encode(rand::thread_rng().gen::<[u8; 16]>()),
+            }
+        } else {
+            Privacy::Keep
+        }
+    }
+}
+
+#[test]
+fn test_merge() {
+    let lower = Preset {
+        langs: vec!["ja".to_string()],
+        ng_words: vec!["a".to_string()],
+        anonymize: Some(true),
+        ..Preset::default()
+    };
+    let merged = lower.clone().merge(Preset {
+        langs: vec!["en".to_string()],
+        ng_words: vec!["b".to_string()],
+        ..Preset::default()
+    });
+    assert_eq!(vec!["en".to_string()], merged.langs);
+    assert_eq!(vec!["a".to_string(), "b".to_string()], merged.ng_words);
+    assert_eq!(Some(true), merged.anonymize);
+    assert_eq!(vec!["ja".to_string()], lower.merge(Preset::default()).langs);
+}
@@ -0,0 +1,176 @@
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::{
+    io::{stderr, Write},
+    time::{Duration, Instant},
+};
+use twinicodo::twitter::Tweet;
+
+/// How progress of searches is reported on stderr.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// A line updated in place for each page
+    Text,
+    /// A new line for each page, used when stderr is not a terminal or jobs run in parallel
+    Lines,
+    Quiet,
+    /// A JSON object per line for each page
+    Json,
+}
+
+impl Mode {
+    /// `Text` when stderr is a terminal, and `Lines` otherwise.
+    pub fn text() -> Self {
+        if atty::is(atty::Stream::Stderr) {
+            Mode::Text
+        } else {
+            Mode::Lines
+        }
+    }
+
+    /// Whether messages are written for humans.
+    pub fn is_text(self) -> bool {
+        self == Mode::Text || self == Mode::Lines
+    }
+}
+
+/// Statistics of a search, which goes back from `until` to `since`.
+#[derive(Debug)]
+pub struct Progress {
+    mode: Mode,
+    label: Option<String>,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    started: Instant,
+    pages: usize,
+    tweets: usize,
+    oldest: Option<DateTime<Utc>>,
+}
+
+impl Progress {
+    pub fn new(
+        mode: Mode,
+        label: Option<String>,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            mode,
+            label,
+            since,
+            until,
+            started: Instant::now(),
+            pages: 0,
+            tweets: 0,
+            oldest: None,
+        }
+    }
+
+    pub fn page(&mut self, tweets: &[Tweet]) {
+        self.pages += 1;
+        self.tweets += tweets.len();
+        self.oldest = tweets
+            .iter()
+            .filter_map(|t| t.created_at)
+            .chain(self.oldest)
+            .min();
+
+        match self.mode {
+            Mode::Text => {
+                eprint!("\r\x1b[2K{}{}", self.prefix(), self.text());
+                let _ = stderr().flush();
+            }
+            Mode::Lines => eprintln!("{}{}", self.prefix(), self.text()),
+            Mode::Json => eprintln!("{}", self.json("page")),
+            Mode::Quiet => {}
+        }
+    }
+
+    pub fn finish(&self) {
+        match self.mode {
+            Mode::Text if self.pages > 0 => eprintln!(),
+            Mode::Json => eprintln!("{}", self.json("finish")),
+            _ => {}
+        }
+    }
+
+    fn prefix(&self) -> String {
+        self.label
+            .as_ref()
+            .map(|l| format!("{}: ", l))
+            .unwrap_or_default()
+    }
+
+    /// Ratio of the searched time to the whole window
+    fn ratio(&self) -> Option<f64> {
+        let total = (self.until - self.since).num_milliseconds();
+        let done = (self.until - self.oldest?).num_milliseconds();
+        if total <= 0 {
+            return None;
+        }
+        Some((done as f64 / total as f64).clamp(0.0, 1.0))
+    }
+
+    fn rate(&self) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.tweets as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        let ratio = self.ratio()?;
+        if ratio <= 0.0 {
+            return None;
+        }
+        Some(self.started.elapsed().mul_f64((1.0 - ratio) / ratio))
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "{} pages, {} tweets, reached {} ({}), {:.1} tweets/s, ETA {}",
+            self.pages,
+            self.tweets,
+            self.oldest
+                .map(|d| d.to_rfc3339())
+                .unwrap_or_else(|| "-".to_string()),
+            self.ratio()
+                .map(|r| format!("{:.0}%", r * 100.0))
+                .unwrap_or_else(|| "-".to_string()),
+            self.rate(),
+            self.eta()
+                .map(|d| format!("{}:{:02}", d.as_secs() / 60, d.as_secs() % 60))
+                .unwrap_or_else(|| "-".to_string()),
+        )
+    }
+
+    fn json(&self, event: &str) -> serde_json::Value {
+        json!({
+            "event": event,
+            "job": self.label,
+            "pages": self.pages,
+            "tweets": self.tweets,
+            "oldest": self.oldest,
+            "since": self.since,
+            "progress": self.ratio(),
+            "rate": self.rate(),
+            "eta": self.eta().map(|d| d.as_secs()),
+        })
+    }
+}
+
+#[test]
+fn test_progress_ratio() {
+    use chrono::TimeZone;
+    let mut progress = Progress::new(
+        Mode::Quiet,
+        None,
+        Utc.ymd(2020, 8, 1).and_hms(0, 0, 0),
+        Utc.ymd(2020, 8, 2).and_hms(0, 0, 0),
+    );
+    assert_eq!(None, progress.ratio());
+    progress.oldest = Some(Utc.ymd(2020, 8, 1).and_hms(18, 0, 0));
+    assert_eq!(Some(0.25), progress.ratio());
+}
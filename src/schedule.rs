@@ -0,0 +1,104 @@
+use crate::batch::JobSpec;
+use chrono::{Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+/// A recurring broadcast such as every Sunday 23:00 in Asia/Tokyo for 30 minutes,
+/// which is expanded into a job for each episode.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Schedule {
+    /// Prefix of job names. The query is used when not specified.
+    pub name: Option<String>,
+    pub query: String,
+    /// Date of the first episode
+    pub start: NaiveDate,
+    /// Start time of episodes like `23:00:00`
+    pub time: NaiveTime,
+    /// Timezone of `start` and `time` like `Asia/Tokyo`
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+    /// Length of an episode in minutes
+    pub duration: i64,
+    /// Days between episodes
+    #[serde(default = "default_interval")]
+    pub interval: i64,
+    /// Number of episodes
+    pub episodes: u32,
+    /// Number of the first episode
+    #[serde(default = "default_first_episode")]
+    pub first_episode: u32,
+    /// Minutes searched before and after each episode
+    #[serde(default)]
+    pub margin: i64,
+    pub preset: Option<String>,
+    pub output: Option<String>,
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+fn default_interval() -> i64 {
+    7
+}
+
+fn default_first_episode() -> u32 {
+    1
+}
+
+impl Schedule {
+    /// Jobs searching around each episode with anchors at the start of episodes.
+    /// Episodes which do not exist in the timezone due to DST are skipped.
+    pub fn jobs(&self) -> Vec<JobSpec> {
+        let name = self.name.as_ref().unwrap_or(&self.query);
+        (0..self.episodes)
+            .filter_map(|i| {
+                let date = self.start + Duration::days(self.interval * i as i64);
+                let start = self
+                    .timezone
+                    .from_local_datetime(&date.and_time(self.time))
+                    .earliest()?
+                    .with_timezone(&Utc);
+                let since = start - Duration::minutes(self.margin);
+                let until = start + Duration::minutes(self.duration + self.margin);
+                let episode = self.first_episode + i;
+                Some(JobSpec {
+                    name: Some(format!("{}#{}", name, episode)),
+                    query: self.query.clone(),
+                    queries: Vec::new(),
+                    since: since.to_rfc3339(),
+                    until: until.to_rfc3339(),
+                    anchor: Some(start.to_rfc3339()),
+                    episode: Some(episode),
+                    preset: self.preset.clone(),
+                    output: self.output.clone(),
+                    archive: None,
+                })
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_schedule_jobs() {
+    let schedule: Schedule = toml::from_str(
+        r##"
+        query = "#anime"
+        start = "2020-07-05"
+        time = "23:00:00"
+        timezone = "Asia/Tokyo"
+        duration = 30
+        episodes = 2
+        first_episode = 3
+        margin = 5
+        "##,
+    )
+    .unwrap();
+    let jobs = schedule.jobs();
+    assert_eq!(2, jobs.len());
+    assert_eq!(Some("#anime#4"), jobs[1].name.as_deref());
+    assert_eq!("2020-07-12T13:55:00+00:00", jobs[1].since);
+    assert_eq!("2020-07-12T14:35:00+00:00", jobs[1].until);
+    assert_eq!(Some("2020-07-12T14:00:00+00:00"), jobs[1].anchor.as_deref());
+    assert_eq!(Some(4), jobs[1].episode);
+}
@@ -0,0 +1,191 @@
+use aes_gcm::{
+    aead::{generic_array::GenericArray, Aead, NewAead},
+    Aes256Gcm,
+};
+use derive_more::From;
+use hmac::Hmac;
+use rand::{rngs::OsRng, RngCore};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    env,
+    fs::{create_dir_all, read_to_string, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+const APP_NAME: &str = env!("CARGO_PKG_NAME");
+const PASSPHRASE_ENV: &str = "TWINICODO_PASSPHRASE";
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+/// Where the secret fields of profiles are kept. Non-secret settings always stay in the confy file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Storage {
+    #[default]
+    Plain,
+    Encrypted {
+        path: Option<PathBuf>,
+    },
+    File {
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Error, From)]
+pub enum Error {
+    #[error("{0}")]
+    IO(std::io::Error),
+    #[error("{0}")]
+    TomlDe(toml::de::Error),
+    #[error("{0}")]
+    TomlSer(toml::ser::Error),
+    #[error("{0}")]
+    Base64(base64::DecodeError),
+    #[error("wrong passphrase or broken secrets file")]
+    Decrypt,
+    #[error("config directory not found")]
+    ConfigDir,
+    #[error("passphrase required: set {}", PASSPHRASE_ENV)]
+    PassphraseRequired,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+impl Storage {
+    /// Returns `None` when secrets are stored together with the config.
+    pub fn load<T: DeserializeOwned>(
+        &self,
+        passphrase: &mut Option<String>,
+        interactive: bool,
+    ) -> Result<Option<T>, Error> {
+        let s = match self {
+            Self::Plain => return Ok(None),
+            Self::File { path } => read_to_string(path)?,
+            Self::Encrypted { path } => {
+                let path = encrypted_path(path)?;
+                if !path.exists() {
+                    return Ok(Some(toml::from_str("")?));
+                }
+                let e = toml::from_str::<Envelope>(&read_to_string(path)?)?;
+                let p = get_passphrase(passphrase, interactive, false)?;
+                String::from_utf8(decrypt(&e, &p)?).map_err(|_| Error::Decrypt)?
+            }
+        };
+        Ok(Some(toml::from_str(&s)?))
+    }
+
+    pub fn store<T: Serialize>(
+        &self,
+        secrets: &T,
+        passphrase: &mut Option<String>,
+        interactive: bool,
+    ) -> Result<(), Error> {
+        let s = toml::to_string(secrets)?;
+        match self {
+            Self::Plain => {}
+            Self::File { path } => write_private(path, s.as_bytes())?,
+            Self::Encrypted { path } => {
+                let path = encrypted_path(path)?;
+                if let Some(dir) = path.parent() {
+                    create_dir_all(dir)?;
+                }
+                let p = get_passphrase(passphrase, interactive, !path.exists())?;
+                write_private(
+                    &path,
+                    toml::to_string(&encrypt(s.as_bytes(), &p))?.as_bytes(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a file readable only by the owner.
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut o = OpenOptions::new();
+    o.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut o, 0o600);
+    o.open(path)?.write_all(data)
+}
+
+fn encrypted_path(path: &Option<PathBuf>) -> Result<PathBuf, Error> {
+    if let Some(path) = path {
+        return Ok(path.clone());
+    }
+    directories::ProjectDirs::from("rs", "", APP_NAME)
+        .map(|p| p.config_dir().join("secrets.enc"))
+        .ok_or(Error::ConfigDir)
+}
+
+fn get_passphrase(
+    cache: &mut Option<String>,
+    interactive: bool,
+    confirm: bool,
+) -> Result<String, Error> {
+    if let Some(p) = cache.as_ref() {
+        return Ok(p.clone());
+    }
+    let p = if let Ok(p) = env::var(PASSPHRASE_ENV) {
+        p
+    } else if !interactive {
+        return Err(Error::PassphraseRequired);
+    } else {
+        let mut input = dialoguer::Password::new();
+        input.with_prompt("Passphrase for secrets");
+        if confirm {
+            input.with_confirmation("Confirm passphrase", "Passphrases mismatch");
+        }
+        input.interact()?
+    };
+    *cache = Some(p.clone());
+    Ok(p)
+}
+
+fn key(passphrase: &str, salt: &[u8]) -> Aes256Gcm {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    Aes256Gcm::new(GenericArray::from_slice(&key))
+}
+
+fn encrypt(data: &[u8], passphrase: &str) -> Envelope {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+    let data = key(passphrase, &salt)
+        .encrypt(GenericArray::from_slice(&nonce), data)
+        .expect("encryption failure");
+    Envelope {
+        salt: base64::encode(salt),
+        nonce: base64::encode(nonce),
+        data: base64::encode(data),
+    }
+}
+
+fn decrypt(e: &Envelope, passphrase: &str) -> Result<Vec<u8>, Error> {
+    let nonce = base64::decode(&e.nonce)?;
+    if nonce.len() != 12 {
+        return Err(Error::Decrypt);
+    }
+    key(passphrase, &base64::decode(&e.salt)?)
+        .decrypt(
+            GenericArray::from_slice(&nonce),
+            &base64::decode(&e.data)? as &[u8],
+        )
+        .map_err(|_| Error::Decrypt)
+}
+
+#[test]
+fn test_encrypt() {
+    let e = encrypt(b"secret", "pass");
+    assert_eq!(b"secret".to_vec(), decrypt(&e, "pass").unwrap());
+    assert!(decrypt(&e, "wrong").is_err());
+}
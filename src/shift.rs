@@ -0,0 +1,123 @@
+use std::str::FromStr;
+use thiserror::Error;
+use twinicodo::nicodo::Chat;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid time: {0}")]
+    Time(String),
+    #[error("invalid segment, expected FROM-TO=OFFSET like 1:30-10:00=-5: {0}")]
+    Segment(String),
+}
+
+/// A part of the source timeline moved by the offset in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub from: f64,
+    pub to: f64,
+    pub offset: f64,
+}
+
+impl FromStr for Segment {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || Error::Segment(s.to_string());
+        let (range, offset) = s.split_once('=').ok_or_else(err)?;
+        let (from, to) = range.split_once('-').ok_or_else(err)?;
+        Ok(Self {
+            from: parse_time(from)?,
+            to: parse_time(to)?,
+            offset: parse_time(offset)?,
+        })
+    }
+}
+
+/// Parses seconds like `90.5`, `-5`, `1:30.5` or `1:01:30`.
+pub fn parse_time(s: &str) -> Result<f64, Error> {
+    let (sign, abs) = match s.trim() {
+        t if t.starts_with('-') => (-1.0, &t[1..]),
+        t if t.starts_with('+') => (1.0, &t[1..]),
+        t => (1.0, t),
+    };
+    abs.split(':')
+        .try_fold(0.0, |acc, p| p.parse::<f64>().map(|n| acc * 60.0 + n))
+        .ok()
+        .filter(|n| n.is_finite())
+        .map(|n| sign * n)
+        .ok_or_else(|| Error::Time(s.to_string()))
+}
+
+/// Changes of vpos. The segment map is applied to source times first,
+/// and then they are multiplied by the scale and moved by the offset in seconds.
+#[derive(Debug, Clone)]
+pub struct Retime {
+    pub segments: Vec<Segment>,
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl Retime {
+    /// Returns the new vpos, or `None` when the chat is out of segments or before zero.
+    fn vpos(&self, vpos: u64) -> Option<u64> {
+        let t = vpos as f64 / 100.0;
+        let t = if self.segments.is_empty() {
+            t
+        } else {
+            let s = self.segments.iter().find(|s| s.from <= t && t < s.to)?;
+            t + s.offset
+        };
+        let t = t * self.scale + self.offset;
+        if t < 0.0 {
+            return None;
+        }
+        Some((t * 100.0).round() as u64)
+    }
+
+    /// Applies to chats and keeps them sorted by vpos. Dropped chats are not returned.
+    pub fn apply(&self, chats: Vec<Chat>) -> Vec<Chat> {
+        let mut chats = chats
+            .into_iter()
+            .filter_map(|c| {
+                Some(Chat {
+                    vpos: self.vpos(c.vpos)?,
+                    ..c
+                })
+            })
+            .collect::<Vec<_>>();
+        chats.sort_by_key(|c| (c.vpos, c.date));
+        chats
+    }
+}
+
+#[test]
+fn test_retime() {
+    assert_eq!(90.5, parse_time("1:30.5").unwrap());
+    assert_eq!(-5.0, parse_time("-5").unwrap());
+    assert!(parse_time("a").is_err());
+    assert_eq!(
+        Segment {
+            from: 90.0,
+            to: 600.0,
+            offset: -30.0
+        },
+        "1:30-10:00=-30".parse().unwrap()
+    );
+
+    let retime = Retime {
+        segments: vec!["0-60=0".parse().unwrap(), "90-200=-30".parse().unwrap()],
+        scale: 1.0,
+        offset: -1.0,
+    };
+    assert_eq!(None, retime.vpos(50));
+    assert_eq!(Some(900), retime.vpos(1000));
+    assert_eq!(None, retime.vpos(7000));
+    assert_eq!(Some(5900), retime.vpos(9000));
+
+    let retime = Retime {
+        segments: Vec::new(),
+        scale: 1001.0 / 1000.0,
+        offset: 0.0,
+    };
+    assert_eq!(Some(100100), retime.vpos(100000));
+}
@@ -0,0 +1,194 @@
+use chrono::{DateTime, TimeZone, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use twinicodo::nicodo::Chat;
+
+/// Length of windows to find peaks in seconds
+const PEAK_WINDOW: u64 = 10;
+const HISTOGRAM_WIDTH: usize = 50;
+
+/// Summary of chats to choose anchors and density caps.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub total: usize,
+    pub retweet_ratio: f64,
+    /// Number of chats in each minute from vpos 0
+    pub per_minute: Vec<usize>,
+    pub top_users: Vec<(String, usize)>,
+    pub top_hashtags: Vec<(String, usize)>,
+    pub top_words: Vec<(String, usize)>,
+    pub peaks: Vec<Peak>,
+}
+
+/// Window of `PEAK_WINDOW` seconds with many chats.
+#[derive(Debug, Serialize)]
+pub struct Peak {
+    pub vpos: u64,
+    pub date: DateTime<Utc>,
+    pub count: usize,
+}
+
+impl Stats {
+    /// Top lists and peaks have at most `top` entries.
+    pub fn new(chats: &[Chat], top: usize) -> Self {
+        lazy_static! {
+            static ref RE_HASHTAG: Regex = Regex::new(r"#[\w_]+").unwrap();
+            // runs of kanji, hiragana, katakana or other letters since Japanese has no spaces
+            static ref RE_WORD: Regex = Regex::new(
+                r"[\p{Han}々]{2,}|\p{Hiragana}{2,}|[\p{Katakana}ー]{2,}|[^\s\p{P}\p{Han}々\p{Hiragana}\p{Katakana}ー]{2,}"
+            )
+            .unwrap();
+            static ref RE_SKIP: Regex = Regex::new(r"(?:https?://|[#@])\S+").unwrap();
+        }
+
+        let mut per_minute = Vec::new();
+        let mut users = HashMap::new();
+        let mut hashtags = HashMap::new();
+        let mut words = HashMap::new();
+        let mut windows = HashMap::<u64, (usize, u64)>::new();
+        let mut retweets = 0;
+
+        for c in chats {
+            let minute = (c.vpos / 6000) as usize;
+            if per_minute.len() <= minute {
+                per_minute.resize(minute + 1, 0);
+            }
+            per_minute[minute] += 1;
+
+            let w = windows
+                .entry(c.vpos / (PEAK_WINDOW * 100))
+                .or_insert((0, c.date));
+            w.0 += 1;
+            w.1 = w.1.min(c.date);
+
+            if let Some(u) = c.user_id.as_ref() {
+                *users.entry(u.to_string()).or_insert(0) += 1;
+            }
+            if c.content.starts_with("RT @") {
+                retweets += 1;
+                continue;
+            }
+            for m in RE_HASHTAG.find_iter(&c.content) {
+                *hashtags.entry(m.as_str().to_string()).or_insert(0) += 1;
+            }
+            for m in RE_WORD.find_iter(&RE_SKIP.replace_all(&c.content, " ")) {
+                *words.entry(m.as_str().to_lowercase()).or_insert(0) += 1;
+            }
+        }
+
+        let mut peaks = windows
+            .into_iter()
+            .map(|(i, (count, date))| Peak {
+                vpos: i * PEAK_WINDOW * 100,
+                date: Utc.timestamp(date as i64, 0),
+                count,
+            })
+            .collect::<Vec<_>>();
+        peaks.sort_by(|a, b| b.count.cmp(&a.count).then(a.vpos.cmp(&b.vpos)));
+        peaks.truncate(top);
+
+        Self {
+            total: chats.len(),
+            retweet_ratio: if chats.is_empty() {
+                0.0
+            } else {
+                retweets as f64 / chats.len() as f64
+            },
+            per_minute,
+            top_users: ranking(users, top),
+            top_hashtags: ranking(hashtags, top),
+            top_words: ranking(words, top),
+            peaks,
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "Comments: {}", self.total)?;
+        writeln!(w, "Retweets: {:.1}%", self.retweet_ratio * 100.0)?;
+
+        writeln!(w, "\nComments per minute:")?;
+        let max = self.per_minute.iter().max().copied().unwrap_or(0).max(1);
+        for (m, n) in self.per_minute.iter().enumerate() {
+            writeln!(
+                w,
+                "{:>4}:00 {:>6} {}",
+                m,
+                n,
+                "#".repeat(n * HISTOGRAM_WIDTH / max)
+            )?;
+        }
+
+        for (title, list) in &[
+            ("Top users", &self.top_users),
+            ("Top hashtags", &self.top_hashtags),
+            ("Top words", &self.top_words),
+        ] {
+            writeln!(w, "\n{}:", title)?;
+            for (k, n) in list.iter() {
+                writeln!(w, "{:>6} {}", n, k)?;
+            }
+        }
+
+        writeln!(w, "\nPeaks ({} seconds):", PEAK_WINDOW)?;
+        for p in &self.peaks {
+            let s = p.vpos / 100;
+            writeln!(
+                w,
+                "{:>6} vpos {} ({}:{:02}) {}",
+                p.count,
+                p.vpos,
+                s / 60,
+                s % 60,
+                p.date.to_rfc3339()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn ranking(map: HashMap<String, usize>, top: usize) -> Vec<(String, usize)> {
+    let mut v = map.into_iter().collect::<Vec<_>>();
+    v.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    v.truncate(top);
+    v
+}
+
+#[test]
+fn test_stats() {
+    let chat = |vpos: u64, user: &str, content: &str| Chat {
+        date: 1596294000 + vpos / 100,
+        vpos,
+        user_id: Some(user.to_string()),
+        id: None,
+        mail: None,
+        content: content.to_string(),
+        ..Chat::default()
+    };
+    let chats = vec![
+        chat(0, "a", "hello #anime"),
+        chat(100, "a", "hello world"),
+        chat(200, "b", "RT @a: hello"),
+        chat(7000, "c", "#anime"),
+    ];
+    let stats = Stats::new(&chats, 1);
+    assert_eq!(4, stats.total);
+    assert_eq!(0.25, stats.retweet_ratio);
+    assert_eq!(vec![3, 1], stats.per_minute);
+    assert_eq!(vec![("a".to_string(), 2)], stats.top_users);
+    assert_eq!(vec![("#anime".to_string(), 2)], stats.top_hashtags);
+    assert_eq!(vec![("hello".to_string(), 2)], stats.top_words);
+    assert_eq!(0, stats.peaks[0].vpos);
+    assert_eq!(3, stats.peaks[0].count);
+
+    let chats = vec![
+        chat(0, "a", "作画が最高すぎる"),
+        chat(100, "b", "今日の作画、最高！"),
+    ];
+    assert_eq!(
+        vec![("作画".to_string(), 2), ("最高".to_string(), 2)],
+        Stats::new(&chats, 2).top_words
+    );
+}
@@ -0,0 +1,182 @@
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime,
+};
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::path::PathBuf;
+use thiserror::Error;
+
+pub const DEFAULT_FILENAME: &str = "{query}_{since}_{until}.{format}";
+
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unknown placeholder in filename template: {0}")]
+    Unknown(String),
+    #[error("invalid date format in filename template: {0}")]
+    DateFormat(String),
+}
+
+/// Values of placeholders in filename templates.
+#[derive(Debug, Default)]
+pub struct Vars<'a> {
+    pub query: &'a str,
+    pub since: &'a str,
+    pub until: &'a str,
+    pub count: usize,
+    pub format: &'a str,
+    pub episode: Option<u32>,
+    /// Time at vpos 0 in the timezone of the preset
+    pub date: Option<DateTime<Tz>>,
+}
+
+impl<'a> Vars<'a> {
+    fn get(&self, key: &str, arg: Option<&str>) -> Option<String> {
+        Some(match key {
+            "query" => self.query.to_string(),
+            "since" => self.since.to_string(),
+            "until" => self.until.to_string(),
+            "count" => self.count.to_string(),
+            "format" => self.format.to_string(),
+            "episode" => self
+                .episode
+                .map(|e| format!("{:01$}", e, arg.and_then(|a| a.parse().ok()).unwrap_or(0)))
+                .unwrap_or_default(),
+            "date" => self
+                .date
+                .map(|d| d.format(arg.unwrap_or("%Y%m%d")).to_string())
+                .unwrap_or_default(),
+            _ => return None,
+        })
+    }
+
+    /// Expands placeholders such as `{query}`, `{date:%Y%m%d}` or `{episode:2}` padded with zeros. Expanded values are sanitized
+    /// so that they are safe as a part of a filename, while `/` in the template makes directories.
+    pub fn render(&self, template: &str) -> Result<PathBuf, Error> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"\{(\w+)(?::([^}]*))?\}").unwrap();
+        }
+
+        let mut unknown = None;
+        let mut invalid = None;
+        let s = RE.replace_all(template, |c: &Captures| {
+            let key = &c[1];
+            let arg = c.get(2).map(|m| m.as_str());
+            // formatting panics with invalid formats, which are checked even without dates
+            if let ("date", Some(a)) = (key, arg) {
+                if StrftimeItems::new(a).any(|i| i == Item::Error) {
+                    invalid.get_or_insert_with(|| a.to_string());
+                    return String::new();
+                }
+            }
+            match self.get(key, arg) {
+                Some(v) => sanitize(&v),
+                None => {
+                    unknown.get_or_insert_with(|| key.to_string());
+                    String::new()
+                }
+            }
+        });
+        if let Some(key) = unknown {
+            return Err(Error::Unknown(key));
+        }
+        if let Some(format) = invalid {
+            return Err(Error::DateFormat(format));
+        }
+
+        let mut path = PathBuf::new();
+        if s.starts_with('/') {
+            path.push("/");
+        }
+        path.extend(s.split('/').filter(|c| !c.is_empty()).map(reserved));
+        Ok(path)
+    }
+}
+
+/// Makes the value safe as a part of a filename. Only the template may refer to
+/// the current or parent directory.
+fn sanitize(s: &str) -> String {
+    if s == "." || s == ".." {
+        return "_".to_string();
+    }
+    s.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_whitespace() || c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Avoids names which Windows does not allow.
+fn reserved(s: &str) -> String {
+    if s == "." || s == ".." {
+        return s.to_string();
+    }
+    let s = s.trim_end_matches(&['.', ' '] as &[char]);
+    let stem = s.split('.').next().unwrap_or("");
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        format!("_{}", s)
+    } else {
+        s.to_string()
+    }
+}
+
+#[test]
+fn test_render() {
+    use chrono::TimeZone;
+
+    let vars = Vars {
+        query: "#anime OR a/b: c",
+        since: "2020-08-01",
+        until: "2020-08-02",
+        count: 10,
+        format: "xml",
+        episode: Some(3),
+        date: None,
+    };
+    assert_eq!(
+        PathBuf::from("#anime_OR_a_b__c_2020-08-01_2020-08-02.xml"),
+        vars.render(DEFAULT_FILENAME).unwrap()
+    );
+    assert_eq!(
+        PathBuf::from("dir/_con.10.xml"),
+        vars.render("dir/con.{count}.{format}").unwrap()
+    );
+    assert_eq!(
+        PathBuf::from("/tmp/2020-08-01.xml"),
+        vars.render("/tmp/{since}.xml").unwrap()
+    );
+    assert_eq!(
+        PathBuf::from("ep03.xml"),
+        vars.render("ep{episode:2}.{format}").unwrap()
+    );
+    assert_eq!(
+        PathBuf::from("../_/_.xml"),
+        Vars {
+            query: "..",
+            since: ".",
+            ..Vars::default()
+        }
+        .render("../{query}/{since}.xml")
+        .unwrap()
+    );
+    assert!(vars.render("{foo}").is_err());
+    assert!(vars.render("{date:%Q}").is_err());
+
+    // 23:30 in Tokyo is still the previous day in UTC
+    let vars = Vars {
+        date: Some(Tz::Asia__Tokyo.ymd(2020, 8, 1).and_hms(23, 30, 0)),
+        ..Vars::default()
+    };
+    assert_eq!(
+        PathBuf::from("20200801.xml"),
+        vars.render("{date}.xml").unwrap()
+    );
+}